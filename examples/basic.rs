@@ -1,9 +1,7 @@
 extern crate discord_ipc;
 use std::path::PathBuf;
 
-use discord_ipc::{
-    command::VoiceChannelSelect, Client, Event, EventSubscribe, FileSaver, OauthScope, Result,
-};
+use discord_ipc::{voice::VoiceRoster, Client, FileSaver, OauthScope, Result};
 use simplelog::{Config, TermLogger};
 
 // const CLIENT_ID: u64 = 0;
@@ -23,21 +21,12 @@ async fn main() -> Result<()> {
         .connect()
         .await?;
     client.set_activity("Activity Name").await?;
-    client.subscribe(EventSubscribe::VoiceChannelSelect).await?;
+    let mut roster = VoiceRoster::new();
+    roster.start(&mut client).await?;
     loop {
-        match client.event().await? {
-            Event::VoiceChannelSelect(VoiceChannelSelect {
-                channel_id: Some(channel_id),
-                ..
-            }) => {
-                client
-                    .subscribe(EventSubscribe::SpeakingStart { channel_id })
-                    .await?;
-                client
-                    .subscribe(EventSubscribe::SpeakingStop { channel_id })
-                    .await?;
-            }
-            _ => (),
+        let event = client.event().await?;
+        for change in roster.handle(&mut client, &event).await? {
+            log::info!("{change:?}");
         }
     }
 }
@@ -0,0 +1,15 @@
+extern crate discord_ipc;
+
+use discord_ipc::{blocking::Client, Result};
+use simplelog::{Config, TermLogger};
+
+const CLIENT_ID: u64 = 1067583828543148164;
+
+fn main() -> Result<()> {
+    TermLogger::init(log::LevelFilter::Trace, Config::default()).unwrap();
+    let mut client = Client::new(CLIENT_ID).connect_blocking()?;
+    client.set_activity("Activity Name")?;
+    loop {
+        println!("{:?}", client.event()?);
+    }
+}
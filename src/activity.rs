@@ -9,9 +9,10 @@ use crate::discord::{Snowflake, UnixTimestamp};
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Activity {
     /// Activity name, not sent when setting activity
-    #[serde(skip_serializing)]
+    #[serde(default, skip_serializing)]
     name: String,
     /// Activity type
+    #[serde(default)]
     r#type: ActivityType,
     /// Informational URL
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -54,6 +55,63 @@ pub struct Activity {
     buttons: Option<Vec<Button>>,
 }
 
+impl Activity {
+    /// Create an empty activity
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the activity type, shown as e.g. "Listening to" before the application name
+    pub fn activity_type(mut self, r#type: ActivityType) -> Self {
+        self.r#type = r#type;
+        self
+    }
+
+    /// Set the start and end times. With an end, Discord shows the time remaining, otherwise the
+    /// time elapsed since the start
+    pub fn timestamps(mut self, start: Option<UnixTimestamp>, end: Option<UnixTimestamp>) -> Self {
+        self.timestamps = Some(TimeStamps { start, end });
+        self
+    }
+
+    /// Set the activity details, the first line under the application name
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    /// Set the activity state, the second line under the application name
+    pub fn state(mut self, state: impl Into<String>) -> Self {
+        self.state = Some(state.into());
+        self
+    }
+
+    /// Set the large image, an asset key or URL, with optional hover text
+    pub fn large_image(mut self, image: impl Into<String>, text: Option<String>) -> Self {
+        let assets = self.assets.get_or_insert_with(Assets::default);
+        assets.large_image = Some(AssetImage(image.into()));
+        assets.large_text = text;
+        self
+    }
+
+    /// Set the small image, an asset key or URL, with optional hover text
+    pub fn small_image(mut self, image: impl Into<String>, text: Option<String>) -> Self {
+        let assets = self.assets.get_or_insert_with(Assets::default);
+        assets.small_image = Some(AssetImage(image.into()));
+        assets.small_text = text;
+        self
+    }
+
+    /// Add a button linking to `url`. Discord shows at most two buttons
+    pub fn button(mut self, label: impl Into<String>, url: impl Into<String>) -> Self {
+        self.buttons.get_or_insert_with(Vec::new).push(Button {
+            label: label.into(),
+            url: url.into(),
+        });
+        self
+    }
+}
+
 impl From<&str> for Activity {
     fn from(s: &str) -> Self {
         Self {
@@ -81,6 +139,7 @@ pub enum ActivityType {
     Competing = 5,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for ActivityType {
     fn default() -> Self {
         Self::Game
@@ -117,7 +176,7 @@ pub struct Party {
 }
 
 /// Images assets for Rich Presence
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Assets {
     /// Large image URL
     large_image: Option<AssetImage>,
@@ -171,11 +230,11 @@ bitfield::bitfield! {
     embedded, set_embedded: 8;
 }
 
-/// Unknown
+/// Button linking to a URL, shown under the activity
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Button {
-    /// Unknown
-    label: String,
-    /// Unknown
-    url: String,
+    /// Button text
+    pub label: String,
+    /// URL opened by the button
+    pub url: String,
 }
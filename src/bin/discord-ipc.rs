@@ -0,0 +1,450 @@
+//! `discord-ipc` command line tool
+//!
+//! Sets Rich Presence, watches events & queries the local Discord client, printing JSON so that
+//! scripts and status bars can consume the output.
+
+use std::{io::Write, path::PathBuf, process::ExitCode, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+use discord_ipc::{
+    activity::Activity, discord::Snowflake, presence::template::PresenceFile,
+    voice::VoiceSettingsPatch, Client, ClientBuilder, Connection, EventSubscribe, FileSaver,
+    OauthScope,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "discord-ipc",
+    version,
+    about = "Control a local Discord client over IPC"
+)]
+struct Cli {
+    /// Application (client) ID
+    #[arg(long, env = "DISCORD_CLIENT_ID")]
+    client_id: u64,
+    /// Application secret, needed for commands other than `presence` and `whoami`
+    #[arg(long, env = "DISCORD_CLIENT_SECRET", conflicts_with = "remote_secret")]
+    secret: Option<String>,
+    /// Server requesting tokens on the application's behalf, instead of `--secret`
+    #[arg(long, env = "DISCORD_REMOTE_SECRET")]
+    remote_secret: Option<String>,
+    /// File to save the refresh token to, to avoid authorizing every time
+    #[arg(long, env = "DISCORD_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+    /// Additional OAuth scope, e.g. `rpc.notifications.read`
+    #[arg(long = "scope", value_parser = parse_scope)]
+    scopes: Vec<OauthScope>,
+    /// Seconds to wait for each response
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Set or clear Rich Presence
+    #[command(subcommand)]
+    Presence(Presence),
+    /// Watch events
+    #[command(subcommand)]
+    Events(Events),
+    /// List guilds, one JSON object per line
+    Guilds,
+    /// List the channels of a guild, one JSON object per line
+    Channels {
+        /// Guild ID
+        guild: Snowflake,
+    },
+    /// Get or change voice settings
+    #[command(subcommand)]
+    Voice(Voice),
+    /// Print the connected user
+    Whoami,
+}
+
+#[derive(Debug, Subcommand)]
+enum Presence {
+    /// Set the activity, printing it. Discord clears the activity when the connection closes, so
+    /// this runs until interrupted unless `--exit` is given
+    Set(PresenceArgs),
+    /// Clear the activity
+    Clear,
+    /// Keep the activity in sync with a template file, see `discord_ipc::presence::template`
+    Watch {
+        /// JSON template, or TOML with a `.toml` extension
+        file: PathBuf,
+        /// Value for `{var.NAME}` placeholders, as `NAME=value`
+        #[arg(long = "var", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+    },
+    /// Show the current song from MPRIS media players
+    #[cfg(feature = "mpris")]
+    Mpris {
+        /// Only use players whose bus name contains this, e.g. `spotify`
+        #[arg(long)]
+        player: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct PresenceArgs {
+    /// First line
+    #[arg(long)]
+    details: Option<String>,
+    /// Second line
+    #[arg(long)]
+    state: Option<String>,
+    /// Large image asset key or URL
+    #[arg(long)]
+    large_image: Option<String>,
+    /// Large image hover text
+    #[arg(long, requires = "large_image")]
+    large_text: Option<String>,
+    /// Small image asset key or URL
+    #[arg(long)]
+    small_image: Option<String>,
+    /// Small image hover text
+    #[arg(long, requires = "small_image")]
+    small_text: Option<String>,
+    /// Button, as `label=url`. Can be given twice
+    #[arg(long = "button", value_parser = parse_button)]
+    buttons: Vec<(String, String)>,
+    /// Exit once the activity is set, which clears it
+    #[arg(long)]
+    exit: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Events {
+    /// Print events as JSON lines, `{"evt": ..., "data": ...}`
+    Watch {
+        /// Events to subscribe to, e.g. `VOICE_CHANNEL_SELECT`
+        #[arg(required = true)]
+        events: Vec<String>,
+        /// Channel, for channel events such as `SPEAKING_START`
+        #[arg(long)]
+        channel: Option<Snowflake>,
+        /// Guild, for `GUILD_STATUS`
+        #[arg(long)]
+        guild: Option<Snowflake>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum Voice {
+    /// Print the voice settings
+    Get,
+    /// Change voice settings, printing the new settings
+    Set {
+        /// Mute
+        #[arg(long)]
+        mute: Option<bool>,
+        /// Deafen
+        #[arg(long)]
+        deaf: Option<bool>,
+        /// Input volume, 0 to 100
+        #[arg(long)]
+        input_volume: Option<f32>,
+        /// Output volume, 0 to 200
+        #[arg(long)]
+        output_volume: Option<f32>,
+        /// Input device ID
+        #[arg(long)]
+        input_device: Option<String>,
+        /// Output device ID
+        #[arg(long)]
+        output_device: Option<String>,
+    },
+}
+
+fn parse_button(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((label, url)) if !label.is_empty() && !url.is_empty() => {
+            Ok((label.to_string(), url.to_string()))
+        }
+        _ => Err("expected `label=url`".to_string()),
+    }
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err("expected `NAME=value`".to_string()),
+    }
+}
+
+fn parse_scope(s: &str) -> Result<OauthScope, String> {
+    serde_json::from_value(Value::String(s.to_string())).map_err(|_| format!("unknown scope {s}"))
+}
+
+/// Subscription for the event named `evt`, using the channel or guild if the event needs one
+fn subscription(
+    evt: &str,
+    channel: Option<Snowflake>,
+    guild: Option<Snowflake>,
+) -> Result<EventSubscribe, String> {
+    let evt = evt.to_uppercase();
+    serde_json::from_value(json!({ "evt": evt })).or_else(|_| {
+        let mut args = serde_json::Map::new();
+        if let Some(channel) = channel {
+            args.insert("channel_id".into(), json!(channel));
+        }
+        if let Some(guild) = guild {
+            args.insert("guild_id".into(), json!(guild));
+        }
+        serde_json::from_value(json!({ "evt": evt, "args": args }))
+            .map_err(|_| format!("unknown event {evt}, or missing --channel or --guild"))
+    })
+}
+
+fn print(value: &impl Serialize) -> discord_ipc::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Client configured from the command line options
+fn builder(cli: &Cli, scopes: &[OauthScope]) -> ClientBuilder {
+    let mut builder = Client::new(cli.client_id)
+        .timeout(Duration::from_secs(cli.timeout))
+        // `events watch` prints the raw data of each event
+        .keep_raw_json(matches!(cli.command, Command::Events(_)));
+    if let Some(secret) = &cli.secret {
+        builder = builder.secret(secret);
+    } else if let Some(server) = &cli.remote_secret {
+        builder = builder.remote_secret(server);
+    }
+    if let Some(path) = &cli.token_file {
+        builder = builder.save_token(FileSaver { path: path.clone() });
+    }
+    for scope in cli.scopes.iter().chain(scopes) {
+        builder = builder.scope(*scope);
+    }
+    builder
+}
+
+async fn connect(cli: &Cli, scopes: &[OauthScope]) -> discord_ipc::Result<Client<impl Connection>> {
+    builder(cli, scopes).connect().await
+}
+
+/// Subscribe to `subscriptions`, printing each event received as a JSON line
+async fn watch(
+    client: &mut Client<impl Connection>,
+    subscriptions: Vec<EventSubscribe>,
+    mut out: impl Write,
+) -> discord_ipc::Result<()> {
+    for subscription in subscriptions {
+        client.subscribe(subscription).await?;
+    }
+    loop {
+        let (event, data) = client.event_with_raw().await?;
+        serde_json::to_writer(&mut out, &json!({ "evt": event.evt(), "data": data }))?;
+        writeln!(out)?;
+        out.flush()?;
+    }
+}
+
+async fn run(cli: Cli) -> discord_ipc::Result<()> {
+    match &cli.command {
+        Command::Presence(Presence::Set(args)) => {
+            let mut activity = Activity::new();
+            if let Some(details) = &args.details {
+                activity = activity.details(details);
+            }
+            if let Some(state) = &args.state {
+                activity = activity.state(state);
+            }
+            if let Some(image) = &args.large_image {
+                activity = activity.large_image(image, args.large_text.clone());
+            }
+            if let Some(image) = &args.small_image {
+                activity = activity.small_image(image, args.small_text.clone());
+            }
+            for (label, url) in &args.buttons {
+                activity = activity.button(label, url);
+            }
+            let mut client = connect(&cli, &[]).await?;
+            print(&client.set_activity(activity).await?)?;
+            if !args.exit {
+                tokio::signal::ctrl_c().await?;
+            }
+        }
+        Command::Presence(Presence::Clear) => {
+            connect(&cli, &[]).await?.clear_activity().await?;
+        }
+        Command::Presence(Presence::Watch { file, vars }) => {
+            let mut file = PresenceFile::open(file)?;
+            for (name, value) in vars {
+                file.vars().set(name, value);
+            }
+            file.run(&mut connect(&cli, &[]).await?).await?;
+        }
+        #[cfg(feature = "mpris")]
+        Command::Presence(Presence::Mpris { player }) => {
+            let mut mpris = discord_ipc::mpris::Mpris::session().await?;
+            if let Some(player) = player {
+                mpris = mpris.player(player);
+            }
+            mpris.run(&mut connect(&cli, &[]).await?).await?;
+        }
+        Command::Events(Events::Watch {
+            events,
+            channel,
+            guild,
+        }) => {
+            let subscriptions = events
+                .iter()
+                .map(|evt| subscription(evt, *channel, *guild))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(discord_ipc::Error::InvalidEvent)?;
+            let mut client = connect(
+                &cli,
+                &[OauthScope::RpcVoiceRead, OauthScope::RpcNotificationsRead],
+            )
+            .await?;
+            watch(&mut client, subscriptions, std::io::stdout().lock()).await?;
+        }
+        Command::Guilds => {
+            for guild in connect(&cli, &[]).await?.get_guilds().await?.guilds {
+                print(&guild)?;
+            }
+        }
+        Command::Channels { guild } => {
+            let mut client = connect(&cli, &[]).await?;
+            for channel in client.get_channels(*guild).await?.channels {
+                print(&channel)?;
+            }
+        }
+        Command::Voice(Voice::Get) => {
+            let mut client = connect(&cli, &[OauthScope::RpcVoiceRead]).await?;
+            print(&client.get_voice_settings().await?)?;
+        }
+        Command::Voice(Voice::Set {
+            mute,
+            deaf,
+            input_volume,
+            output_volume,
+            input_device,
+            output_device,
+        }) => {
+            let mut settings = VoiceSettingsPatch::new();
+            if let Some(mute) = mute {
+                settings = settings.mute(*mute);
+            }
+            if let Some(deaf) = deaf {
+                settings = settings.deaf(*deaf);
+            }
+            if let Some(volume) = input_volume {
+                settings = settings.input_volume(*volume);
+            }
+            if let Some(volume) = output_volume {
+                settings = settings.output_volume(*volume);
+            }
+            if let Some(device) = input_device {
+                settings = settings.input_device(device);
+            }
+            if let Some(device) = output_device {
+                settings = settings.output_device(device);
+            }
+            let mut client =
+                connect(&cli, &[OauthScope::RpcVoiceRead, OauthScope::RpcVoiceWrite]).await?;
+            print(&client.set_voice_settings(settings).await?)?;
+        }
+        Command::Whoami => {
+            print(connect(&cli, &[]).await?.user())?;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("discord-ipc: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+#[path = "../mock.rs"]
+mod mock;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        assert_eq!(
+            parse_button("Website=https://example.com/?a=b"),
+            Ok(("Website".into(), "https://example.com/?a=b".into()))
+        );
+        assert!(parse_button("Website").is_err());
+        assert_eq!(parse_var("x="), Ok(("x".into(), String::new())));
+        assert_eq!(parse_scope("rpc.voice.read"), Ok(OauthScope::RpcVoiceRead));
+
+        assert_eq!(
+            subscription("voice_channel_select", None, None),
+            Ok(EventSubscribe::VoiceChannelSelect)
+        );
+        assert_eq!(
+            subscription("SPEAKING_START", Some(Snowflake::from(5)), None),
+            Ok(EventSubscribe::SpeakingStart {
+                channel_id: Snowflake::from(5)
+            })
+        );
+        assert!(subscription("SPEAKING_START", None, None).is_err());
+        assert!(subscription("NOT_AN_EVENT", None, None).is_err());
+
+        Cli::parse_from([
+            "discord-ipc",
+            "--client-id",
+            "42",
+            "presence",
+            "set",
+            "--details",
+            "Testing",
+            "--button",
+            "a=b",
+        ]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn events_watch_prints_data() {
+        use crate::mock::{tokio_socket, MockDiscord};
+
+        let cli = Cli::parse_from(["discord-ipc", "--client-id", "42", "events", "watch", "x"]);
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.expect_command("SUBSCRIBE");
+            discord.respond("SUBSCRIBE", json!({}));
+            discord.dispatch("VOICE_CHANNEL_SELECT", json!({ "channel_id": "5" }));
+        });
+        let mut client = builder(&cli, &[])
+            .connect_with(tokio_socket(socket))
+            .await
+            .unwrap();
+        let mut out = vec![];
+        let res = watch(
+            &mut client,
+            vec![EventSubscribe::VoiceChannelSelect],
+            &mut out,
+        )
+        .await;
+        assert!(matches!(res, Err(discord_ipc::Error::PipeClosed)));
+        assert_eq!(
+            serde_json::from_slice::<Value>(&out).unwrap(),
+            json!({ "evt": "VOICE_CHANNEL_SELECT", "data": { "channel_id": "5" } })
+        );
+        discord.join().unwrap();
+    }
+}
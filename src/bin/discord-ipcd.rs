@@ -0,0 +1,337 @@
+//! `discord-ipcd` presence daemon
+//!
+//! Holds a single connection to Discord and accepts presence from several local programs over a
+//! Unix socket, combining them with
+//! [`PresenceSources`](discord_ipc::presence::PresenceSources). Requests and responses are JSON
+//! lines, see [`ControlRequest`](discord_ipc::presence::ControlRequest). A connection's presence
+//! is removed when it disconnects.
+
+#[cfg(unix)]
+mod daemon {
+    use std::{
+        fs::{self, DirBuilder, Permissions},
+        io,
+        os::unix::fs::{DirBuilderExt, PermissionsExt},
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use clap::Parser;
+    use discord_ipc::{
+        metrics,
+        presence::{ControlRequest, ControlResponse, MergePolicy, PresenceSources, PresenceUpdate},
+        Client, Connection,
+    };
+    use log::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+        signal::unix::{signal, SignalKind},
+        sync::mpsc,
+    };
+
+    #[derive(Debug, Parser)]
+    #[command(
+        name = "discord-ipcd",
+        version,
+        about = "Share one Discord presence between several programs"
+    )]
+    pub struct Args {
+        /// Application (client) ID
+        #[arg(long, env = "DISCORD_CLIENT_ID")]
+        client_id: u64,
+        /// Control socket, defaults to `$XDG_RUNTIME_DIR/discord-ipcd.sock`
+        #[arg(long, env = "DISCORD_IPCD_SOCKET")]
+        socket: Option<PathBuf>,
+        /// How presence from several programs is combined
+        #[arg(long, value_enum, default_value_t = Policy::Merge)]
+        policy: Policy,
+        /// Seconds between attempts to connect to Discord
+        #[arg(long, default_value_t = 5)]
+        retry: u64,
+    }
+
+    #[derive(Debug, Clone, Copy, clap::ValueEnum)]
+    enum Policy {
+        /// Only show the highest priority presence
+        Highest,
+        /// Fill fields missing from the highest priority presence from lower priorities
+        Merge,
+    }
+
+    impl From<Policy> for MergePolicy {
+        fn from(policy: Policy) -> Self {
+            match policy {
+                Policy::Highest => Self::Highest,
+                Policy::Merge => Self::Merge,
+            }
+        }
+    }
+
+    /// Presence update from a control connection, `None` when it's removed
+    type Update = (u64, Option<(i32, PresenceUpdate)>);
+
+    enum Step {
+        Update(Update),
+        /// Name of an event, which the daemon doesn't need
+        Event(String),
+        Disconnected(discord_ipc::Error),
+        Retry,
+        Exit,
+    }
+
+    pub fn default_socket() -> PathBuf {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("discord-ipcd.sock")
+    }
+
+    /// Bind the control socket, replacing a stale socket left by a daemon that didn't exit
+    /// cleanly
+    fn bind(path: &Path) -> io::Result<UnixListener> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another daemon", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        // Bound in a private directory and moved into place once only the user can connect
+        let dir = path.with_file_name(format!(".discord-ipcd-{}", std::process::id()));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        let bound = dir.join("sock");
+        let res = UnixListener::bind(&bound).and_then(|listener| {
+            fs::set_permissions(&bound, Permissions::from_mode(0o600))?;
+            fs::rename(&bound, path)?;
+            Ok(listener)
+        });
+        fs::remove_dir_all(&dir)?;
+        res
+    }
+
+    async fn serve(stream: UnixStream, id: u64, updates: mpsc::UnboundedSender<Update>) {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let response = match serde_json::from_str::<ControlRequest>(&line) {
+                Ok(ControlRequest::Set { priority, presence }) => {
+                    let _ = updates.send((id, Some((priority, presence))));
+                    ControlResponse {
+                        ok: true,
+                        error: None,
+                    }
+                }
+                Ok(ControlRequest::Clear) => {
+                    let _ = updates.send((id, None));
+                    ControlResponse {
+                        ok: true,
+                        error: None,
+                    }
+                }
+                Err(e) => ControlResponse {
+                    ok: false,
+                    error: Some(e.to_string()),
+                },
+            };
+            let mut response = serde_json::to_vec(&response).expect("Responses serialize");
+            response.push(b'\n');
+            if write.write_all(&response).await.is_err() {
+                break;
+            }
+        }
+        let _ = updates.send((id, None));
+    }
+
+    async fn connect(client_id: u64) -> Option<Client<impl Connection>> {
+        let res = Client::new(client_id)
+            .timeout(Duration::from_secs(10))
+            .connect()
+            .await;
+        match res {
+            Ok(client) => {
+                info!("Connected to Discord as {}", client.user().username);
+                Some(client)
+            }
+            Err(e) => {
+                debug!("Failed to connect to Discord: {e}");
+                None
+            }
+        }
+    }
+
+    pub async fn run(args: Args) -> io::Result<()> {
+        let path = args.socket.unwrap_or_else(default_socket);
+        let listener = bind(&path)?;
+        info!("Listening on {}", path.display());
+
+        let (tx, mut updates) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut next_id = 0;
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        next_id += 1;
+                        tokio::spawn(serve(stream, next_id, tx.clone()));
+                    }
+                    Err(e) => warn!("Failed to accept a control connection: {e}"),
+                }
+            }
+        });
+
+        let mut sources = PresenceSources::new(args.policy.into());
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut client = connect(args.client_id).await;
+        let mut was_connected = client.is_some();
+        let mut retry = tokio::time::interval(Duration::from_secs(args.retry));
+        retry.reset();
+        loop {
+            let step = tokio::select! {
+                Some(update) = updates.recv() => Step::Update(update),
+                res = async { client.as_mut().unwrap().event().await }, if client.is_some() => {
+                    match res {
+                        Ok(event) => Step::Event(event.evt().to_string()),
+                        Err(e) => Step::Disconnected(e),
+                    }
+                }
+                _ = retry.tick(), if client.is_none() => Step::Retry,
+                _ = tokio::signal::ctrl_c() => Step::Exit,
+                _ = terminate.recv() => Step::Exit,
+            };
+            match step {
+                Step::Update((id, Some((priority, presence)))) => {
+                    sources.set(id, priority, presence)
+                }
+                Step::Update((id, None)) => {
+                    if !sources.remove(&id) {
+                        continue;
+                    }
+                }
+                Step::Event(evt) => {
+                    debug!("Ignoring {evt} event");
+                    continue;
+                }
+                Step::Disconnected(e) => {
+                    warn!("Disconnected from Discord: {e}");
+                    client = None;
+                    continue;
+                }
+                Step::Retry => {
+                    client = connect(args.client_id).await;
+                    if client.is_none() {
+                        continue;
+                    }
+                    if was_connected {
+                        metrics::reconnected();
+                    }
+                    was_connected = true;
+                }
+                Step::Exit => break,
+            }
+            if let Some(c) = &mut client {
+                let res = match sources.merged() {
+                    Some(presence) => c.set_activity(&presence).await.map(|_| ()),
+                    None => c.clear_activity().await,
+                };
+                if let Err(e) = res {
+                    warn!("Failed to update presence: {e}");
+                    if !matches!(e, discord_ipc::Error::Discord(_)) {
+                        client = None;
+                    }
+                }
+            }
+        }
+        fs::remove_file(&path)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use tokio::io::AsyncReadExt;
+
+        use super::*;
+
+        #[tokio::test]
+        async fn binds_a_private_socket() {
+            let dir =
+                std::env::temp_dir().join(format!("discord-ipcd-test-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("control.sock");
+            // Left by a daemon that didn't exit cleanly
+            drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+            let listener = bind(&path).unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+            let (mut client, accepted) =
+                tokio::join!(async { UnixStream::connect(&path).await.unwrap() }, async {
+                    listener.accept().await.unwrap().0
+                });
+            drop(accepted);
+            assert_eq!(client.read(&mut [0]).await.unwrap(), 0);
+            let err = bind(&path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+            drop(listener);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[tokio::test]
+        async fn serves_control_requests() {
+            let (client, server) = UnixStream::pair().unwrap();
+            let (tx, mut updates) = mpsc::unbounded_channel();
+            tokio::spawn(serve(server, 3, tx));
+
+            let (read, mut write) = client.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write
+                .write_all(b"{\"op\": \"set\", \"priority\": 2, \"details\": \"Testing\"}\n")
+                .await
+                .unwrap();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"{"ok":true}"#);
+            let (id, update) = updates.recv().await.unwrap();
+            assert_eq!(id, 3);
+            let (priority, presence) = update.unwrap();
+            assert_eq!(priority, 2);
+            assert_eq!(presence.details.as_deref(), Some("Testing"));
+
+            write.write_all(b"not json\n").await.unwrap();
+            let response: ControlResponse =
+                serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+            assert!(!response.ok && response.error.is_some());
+
+            write.write_all(b"{\"op\": \"clear\"}\n").await.unwrap();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"{"ok":true}"#);
+            assert!(matches!(updates.recv().await, Some((3, None))));
+
+            // Disconnecting removes the presence
+            drop(write);
+            drop(lines);
+            assert!(matches!(updates.recv().await, Some((3, None))));
+        }
+    }
+}
+
+#[cfg(unix)]
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> std::process::ExitCode {
+    use clap::Parser;
+
+    simplelog::TermLogger::init(log::LevelFilter::Info, simplelog::Config::default()).ok();
+    match daemon::run(daemon::Args::parse()).await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("discord-ipcd: {e}");
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() -> std::process::ExitCode {
+    eprintln!("discord-ipcd: control sockets are only supported on Unix");
+    std::process::ExitCode::FAILURE
+}
@@ -0,0 +1,437 @@
+//! Blocking Discord IPC client
+//!
+//! Wraps the async [`Client`](crate::Client) over std sockets (`UnixStream` or named pipes), for
+//! programs that don't run an async runtime. Reads and writes block the calling thread, so
+//! [`ClientBuilder::timeout`] has no effect.
+//!
+//! OAuth token requests are still made with `reqwest`, so when a secret is configured a private
+//! single threaded tokio runtime is started to run them. This means the blocking client must not
+//! be used from within an async context.
+
+use std::{
+    future::Future,
+    io::{self, Read, Write},
+    pin::{pin, Pin},
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use tokio::runtime::Runtime;
+
+use crate::{
+    activity::Activity,
+    cdn::Cdn,
+    channel::PartialUser,
+    command::{
+        EventResponse, GetChannel, GetChannels, GetGuilds, GetVoiceSettings, SetUserVoiceSettings,
+    },
+    discord::Snowflake,
+    platform::PlatformSocket,
+    voice::{
+        self, DeviceMatcher, JoinOptions, Key, Pan, UserVoicePatch, VoiceSettingsPatch, Volume,
+    },
+    ClientBuilder, EventSubscribe, Result,
+};
+
+/// Connection Builder
+///
+/// Includes a socket type & connection strategy
+pub trait ConnectionBuilder {
+    /// Associated Socket type generated when connected
+    type Socket: Connection;
+    /// Connect to a local discord client
+    fn connect() -> io::Result<Self::Socket>;
+}
+
+/// Convience trait for Socket types
+pub trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// Blocking socket driving the async client. Every operation completes before returning
+struct BlockingIo<C>(C);
+
+// The socket is never pinned in place
+impl<C> Unpin for BlockingIo<C> {}
+
+impl<C: Connection> crate::Connection for BlockingIo<C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().0.read(buf))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().0.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().0.flush())
+    }
+}
+
+/// Wakes the thread waiting in `block_on`
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run `future` to completion. OAuth requests need the private runtime, which is started along
+/// with the client if a secret is configured. Anything else completes without waiting on it
+fn block_on<F: Future>(runtime: Option<&Runtime>, future: F) -> F::Output {
+    if let Some(runtime) = runtime {
+        return runtime.block_on(future);
+    }
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// Blocking client construct
+pub struct Client<C> {
+    client: crate::Client<BlockingIo<C>>,
+    runtime: Option<Runtime>,
+}
+
+impl Client<()> {
+    /// Create a new ClientBuilder with the specified client_id. Use
+    /// [`ClientBuilder::connect_blocking`] to connect.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(client_id: u64) -> ClientBuilder {
+        ClientBuilder::new(client_id)
+    }
+}
+
+impl<C: Connection> Client<C> {
+    /// Authenticate over an opened connection
+    fn connect(config: ClientBuilder, connection: C) -> Result<Self> {
+        let runtime = match config.secret {
+            Some(_) => Some(
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?,
+            ),
+            None => None,
+        };
+        let client = block_on(
+            runtime.as_ref(),
+            config.connect_with(BlockingIo(connection)),
+        )?;
+        Ok(Self { client, runtime })
+    }
+
+    /// Update the User's current activity
+    pub fn set_activity(&mut self, activity: impl Into<Activity>) -> Result<Activity> {
+        block_on(self.runtime.as_ref(), self.client.set_activity(activity))
+    }
+
+    /// Clear the User's current activity
+    pub fn clear_activity(&mut self) -> Result<()> {
+        block_on(self.runtime.as_ref(), self.client.clear_activity())
+    }
+
+    /// List the guilds the user is in. Requires the `rpc` scope
+    pub fn get_guilds(&mut self) -> Result<GetGuilds> {
+        block_on(self.runtime.as_ref(), self.client.get_guilds())
+    }
+
+    /// List the channels of a guild. Requires the `rpc` scope
+    pub fn get_channels(&mut self, guild_id: Snowflake) -> Result<GetChannels> {
+        block_on(self.runtime.as_ref(), self.client.get_channels(guild_id))
+    }
+
+    /// Get a channel, including the voice states of its members. Requires the `rpc` scope
+    pub fn get_channel(&mut self, channel_id: Snowflake) -> Result<GetChannel> {
+        block_on(self.runtime.as_ref(), self.client.get_channel(channel_id))
+    }
+
+    /// Get the user's voice settings. Requires the `rpc` scope
+    pub fn get_voice_settings(&mut self) -> Result<GetVoiceSettings> {
+        block_on(self.runtime.as_ref(), self.client.get_voice_settings())
+    }
+
+    /// Change the user's voice settings, returning the new settings. Takes a
+    /// [`VoiceSettingsPatch`] or a [`SetVoiceSettings`](crate::command::SetVoiceSettings).
+    /// Requires the `rpc` scope
+    pub fn set_voice_settings(
+        &mut self,
+        settings: impl Into<VoiceSettingsPatch>,
+    ) -> Result<GetVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.set_voice_settings(settings),
+        )
+    }
+
+    /// Select the input device matching `matcher`, returning the new settings. Requires the `rpc`
+    /// scope
+    pub fn select_input_device(
+        &mut self,
+        matcher: impl Into<DeviceMatcher>,
+    ) -> Result<GetVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.select_input_device(matcher),
+        )
+    }
+
+    /// Select the output device matching `matcher`, returning the new settings. Requires the
+    /// `rpc` scope
+    pub fn select_output_device(
+        &mut self,
+        matcher: impl Into<DeviceMatcher>,
+    ) -> Result<GetVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.select_output_device(matcher),
+        )
+    }
+
+    /// Change the voice settings of another user, returning their new settings. Requires the
+    /// `rpc` scope
+    pub fn set_user_voice_settings(
+        &mut self,
+        user_id: Snowflake,
+        patch: UserVoicePatch,
+    ) -> Result<SetUserVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.set_user_voice_settings(user_id, patch),
+        )
+    }
+
+    /// Change the volume of another user, returning their new settings. Requires the `rpc` scope
+    pub fn set_user_volume(
+        &mut self,
+        user_id: Snowflake,
+        volume: Volume,
+    ) -> Result<SetUserVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.set_user_volume(user_id, volume),
+        )
+    }
+
+    /// Change the left/right balance of another user, returning their new settings. Requires the
+    /// `rpc` scope
+    pub fn set_user_pan(&mut self, user_id: Snowflake, pan: Pan) -> Result<SetUserVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.set_user_pan(user_id, pan),
+        )
+    }
+
+    /// Mute or unmute another user for the current user only, returning their new settings.
+    /// Requires the `rpc` scope
+    pub fn set_user_mute(
+        &mut self,
+        user_id: Snowflake,
+        mute: bool,
+    ) -> Result<SetUserVoiceSettings> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.set_user_mute(user_id, mute),
+        )
+    }
+
+    /// Set the volume of everyone else in the selected voice channel, returning their new
+    /// settings. Returns nothing if the user isn't in a voice channel. Requires the `rpc` scope
+    pub fn set_channel_volume(&mut self, volume: Volume) -> Result<Vec<SetUserVoiceSettings>> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.set_channel_volume(volume),
+        )
+    }
+
+    /// Start recording a shortcut, e.g. for push to talk. The capture iterates over each key
+    /// combination the user presses until it's stopped. Requires the `rpc` scope
+    pub fn capture_shortcut(&mut self) -> Result<ShortcutCapture<'_, C>> {
+        let runtime = self.runtime.as_ref();
+        Ok(ShortcutCapture {
+            capture: block_on(runtime, self.client.capture_shortcut())?,
+            runtime,
+        })
+    }
+
+    /// Get the user's selected voice channel
+    pub fn get_selected_channel(&mut self) -> Result<Option<GetChannel>> {
+        block_on(self.runtime.as_ref(), self.client.get_selected_channel())
+    }
+
+    /// Join a voice channel, returning it. Fails with
+    /// [`Error::ForceRequired`](crate::Error::ForceRequired) if the user is already in a voice
+    /// channel, unless [`JoinOptions::force`] is set. Requires the `rpc` scope
+    pub fn join_voice(
+        &mut self,
+        channel_id: Snowflake,
+        options: JoinOptions,
+    ) -> Result<GetChannel> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.join_voice(channel_id, options),
+        )
+    }
+
+    /// Leave the current voice channel. Requires the `rpc` scope
+    pub fn leave_voice(&mut self) -> Result<()> {
+        block_on(self.runtime.as_ref(), self.client.leave_voice())
+    }
+
+    /// Open a text channel in Discord, returning it. Requires the `rpc` scope
+    pub fn focus_text_channel(&mut self, channel_id: Snowflake) -> Result<GetChannel> {
+        block_on(
+            self.runtime.as_ref(),
+            self.client.focus_text_channel(channel_id),
+        )
+    }
+
+    /// Subscribe to an event. Use `.event()` to wait for events
+    pub fn subscribe(&mut self, event: EventSubscribe) -> Result<()> {
+        block_on(self.runtime.as_ref(), self.client.subscribe(event))
+    }
+
+    /// Unsubscribe from events previously subscribed to
+    pub fn unsubscribe(&mut self, event: EventSubscribe) -> Result<()> {
+        block_on(self.runtime.as_ref(), self.client.unsubscribe(event))
+    }
+
+    /// Wait for a discord event to be sent
+    pub fn event(&mut self) -> Result<EventResponse> {
+        block_on(self.runtime.as_ref(), self.client.event())
+    }
+
+    /// Wait for a discord event to be sent, along with its raw `data`. The data is only kept if
+    /// enabled with [`ClientBuilder::keep_raw_json`]
+    pub fn event_with_raw(&mut self) -> Result<(EventResponse, Option<serde_json::Value>)> {
+        block_on(self.runtime.as_ref(), self.client.event_with_raw())
+    }
+
+    /// User information provided during connection
+    pub fn user(&self) -> &PartialUser {
+        self.client.user()
+    }
+
+    /// Build image URLs on the CDN host Discord sent during connection
+    pub fn cdn(&self) -> Cdn {
+        self.client.cdn()
+    }
+}
+
+/// Shortcut being recorded, see [`Client::capture_shortcut`]
+///
+/// Iterates over each key combination the user presses until [`stop`](Self::stop) is called.
+/// Other events received meanwhile are kept and returned by [`Client::event`] once the capture
+/// is stopped or dropped.
+pub struct ShortcutCapture<'a, C> {
+    capture: voice::ShortcutCapture<'a, BlockingIo<C>>,
+    runtime: Option<&'a Runtime>,
+}
+
+impl<C: Connection> ShortcutCapture<'_, C> {
+    /// Stop recording, returning the skipped events to the client
+    pub fn stop(self) -> Result<()> {
+        block_on(self.runtime, self.capture.stop())
+    }
+}
+
+impl<C: Connection> Iterator for ShortcutCapture<'_, C> {
+    type Item = Result<Vec<Key>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(block_on(self.runtime, self.capture.next()))
+    }
+}
+
+impl ClientBuilder {
+    /// Connect to a running Discord client using a blocking socket and authenticate
+    pub fn connect_blocking(self) -> Result<Client<<PlatformSocket as ConnectionBuilder>::Socket>> {
+        let connection = <PlatformSocket as ConnectionBuilder>::connect()?;
+        Client::connect(self, connection)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{codec::OpCode, command::RpcErrorCode, mock::MockDiscord, Error};
+
+    #[test]
+    fn handshake_and_activity() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.expect_command("SET_ACTIVITY");
+            discord.dispatch(
+                "VOICE_CHANNEL_SELECT",
+                json!({ "channel_id": null, "guild_id": null }),
+            );
+            discord.respond(
+                "SET_ACTIVITY",
+                json!({ "name": "test", "type": 0, "state": "Testing" }),
+            );
+        });
+
+        let mut client = Client::connect(ClientBuilder::new(42), socket).unwrap();
+        assert_eq!(client.user().username, "user");
+        client.set_activity("Testing").unwrap();
+        assert!(matches!(
+            client.event().unwrap(),
+            EventResponse::VoiceChannelSelect(_)
+        ));
+        discord.join().unwrap();
+    }
+
+    #[test]
+    fn errors_and_keep_alive() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            let payload = discord.expect_command("GET_CHANNEL");
+            discord.write_frame(
+                OpCode::FRAME,
+                json!({
+                    "cmd": "GET_CHANNEL",
+                    "evt": "ERROR",
+                    "data": { "code": 4005, "message": "Invalid channel id" },
+                    "nonce": payload["nonce"]
+                }),
+            );
+
+            discord.expect_command("SET_ACTIVITY");
+            discord.write_frame(OpCode::PING, json!({ "nonce": 1 }));
+            assert_eq!(
+                discord.read_frame(),
+                (OpCode::PONG as u32, json!({ "nonce": 1 }))
+            );
+            discord.write_frame(OpCode::PONG, json!({ "nonce": 2 }));
+            discord.respond(
+                "SET_ACTIVITY",
+                json!({ "name": "test", "type": 0, "state": "Testing" }),
+            );
+        });
+
+        let mut client = Client::connect(ClientBuilder::new(42), socket).unwrap();
+        let err = client.get_channel(Snowflake(10)).unwrap_err();
+        assert!(matches!(err, Error::Discord(_)));
+        assert_eq!(err.rpc_code(), Some(RpcErrorCode::InvalidChannel));
+        // The connection is still usable, and answers keep alive pings while waiting
+        client.set_activity("Testing").unwrap();
+        discord.join().unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+//! Discord CDN image URLs
+//!
+//! Hashes such as [`PartialUser::avatar`] only name an image. [`Cdn`] turns them into URLs on the
+//! CDN host Discord sent when connecting, see [`Client::cdn`](crate::Client::cdn).
+//!
+//! ```no_run
+//! # fn f(client: &discord_ipc::Client<impl discord_ipc::Connection>) {
+//! use discord_ipc::cdn::ImageFormat;
+//!
+//! let url = client.cdn().size(128).format(ImageFormat::Webp).user_avatar(client.user());
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::{channel::PartialUser, discord::Snowflake};
+
+/// Image format. Animated images are only available as GIF or WebP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// PNG
+    Png,
+    /// JPEG
+    Jpeg,
+    /// WebP, animated for animated images
+    Webp,
+    /// GIF, only for animated images
+    Gif,
+}
+
+impl ImageFormat {
+    /// File extension
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Gif => "gif",
+        }
+    }
+}
+
+/// CDN URL builder
+///
+/// Images use the chosen [`format`](Self::format), or PNG by default and GIF for animated
+/// images, whose hashes start with `a_`. GIF falls back to PNG for images that aren't animated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cdn {
+    host: String,
+    size: Option<u16>,
+    format: Option<ImageFormat>,
+}
+
+impl Cdn {
+    /// Smallest image size Discord serves
+    pub const MIN_SIZE: u16 = 16;
+    /// Largest image size Discord serves
+    pub const MAX_SIZE: u16 = 4096;
+
+    /// Build URLs on `host`, e.g. `cdn.discordapp.com`
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            size: None,
+            format: None,
+        }
+    }
+
+    /// Request images `size` pixels wide, rounded up to a power of two between
+    /// [`MIN_SIZE`](Self::MIN_SIZE) and [`MAX_SIZE`](Self::MAX_SIZE)
+    pub fn size(mut self, size: u16) -> Self {
+        self.size = Some(
+            size.clamp(Self::MIN_SIZE, Self::MAX_SIZE)
+                .next_power_of_two(),
+        );
+        self
+    }
+
+    /// Request images in `format`
+    pub fn format(mut self, format: ImageFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Avatar of a user, or their default avatar if they haven't set one
+    pub fn user_avatar(&self, user: &PartialUser) -> String {
+        match &user.avatar {
+            Some(hash) => self.avatar(user.id, hash),
+            None => self.default_avatar(user.id, user.nums()),
+        }
+    }
+
+    /// Avatar of a user from its hash
+    pub fn avatar(&self, user_id: Snowflake, hash: &str) -> String {
+        self.image(&format!("avatars/{user_id}/{hash}"), is_animated(hash))
+    }
+
+    /// Default avatar of a user, picked from their ID or for users with a discriminator from
+    /// its numbers. Only available as PNG
+    pub fn default_avatar(&self, user_id: Snowflake, discriminator: Option<u16>) -> String {
+        let index = match discriminator {
+            Some(nums) => u64::from(nums % 5),
+            None => (user_id.0 >> 22) % 6,
+        };
+        format!("https://{}/embed/avatars/{index}.png", self.host)
+    }
+
+    /// Profile banner of a user from its hash
+    pub fn banner(&self, user_id: Snowflake, hash: &str) -> String {
+        self.image(&format!("banners/{user_id}/{hash}"), is_animated(hash))
+    }
+
+    /// Icon of a guild from its hash
+    pub fn guild_icon(&self, guild_id: Snowflake, hash: &str) -> String {
+        self.image(&format!("icons/{guild_id}/{hash}"), is_animated(hash))
+    }
+
+    /// Custom emoji
+    pub fn emoji(&self, emoji_id: Snowflake, animated: bool) -> String {
+        self.image(&format!("emojis/{emoji_id}"), animated)
+    }
+
+    /// Icon of an application from its hash
+    pub fn application_icon(&self, application_id: Snowflake, hash: &str) -> String {
+        self.image(&format!("app-icons/{application_id}/{hash}"), false)
+    }
+
+    /// Rich Presence asset uploaded to an application
+    pub fn application_asset(&self, application_id: Snowflake, asset_id: Snowflake) -> String {
+        self.image(&format!("app-assets/{application_id}/{asset_id}"), false)
+    }
+
+    fn image(&self, path: &str, animated: bool) -> String {
+        let format = match self.format {
+            Some(ImageFormat::Gif) if !animated => ImageFormat::Png,
+            Some(format) => format,
+            None if animated => ImageFormat::Gif,
+            None => ImageFormat::Png,
+        };
+        let mut url = format!("https://{}/{path}.{}", self.host, format.extension());
+        if let Some(size) = self.size {
+            url.push_str(&format!("?size={size}"));
+        }
+        url
+    }
+}
+
+/// Whether an image hash is for an animated image
+fn is_animated(hash: &str) -> bool {
+    hash.starts_with("a_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let cdn = Cdn::new("cdn.example.com");
+        assert_eq!(
+            cdn.avatar(Snowflake(7), "abc"),
+            "https://cdn.example.com/avatars/7/abc.png"
+        );
+        assert_eq!(
+            cdn.clone().size(100).banner(Snowflake(7), "a_abc"),
+            "https://cdn.example.com/banners/7/a_abc.gif?size=128"
+        );
+        assert_eq!(
+            cdn.clone()
+                .size(10000)
+                .format(ImageFormat::Webp)
+                .guild_icon(Snowflake(1), "a_abc"),
+            "https://cdn.example.com/icons/1/a_abc.webp?size=4096"
+        );
+        // Not animated
+        assert_eq!(
+            cdn.clone()
+                .format(ImageFormat::Gif)
+                .emoji(Snowflake(2), false),
+            "https://cdn.example.com/emojis/2.png"
+        );
+        assert_eq!(
+            cdn.clone()
+                .format(ImageFormat::Jpeg)
+                .application_asset(Snowflake(3), Snowflake(4)),
+            "https://cdn.example.com/app-assets/3/4.jpg"
+        );
+
+        let mut user = PartialUser {
+            username: "user".into(),
+            global_name: None,
+            discriminator: Some("0043".into()),
+            id: Snowflake(175928847299117063),
+            avatar: None,
+        };
+        assert_eq!(
+            cdn.user_avatar(&user),
+            "https://cdn.example.com/embed/avatars/3.png"
+        );
+        user.discriminator = None;
+        assert_eq!(
+            cdn.user_avatar(&user),
+            "https://cdn.example.com/embed/avatars/2.png"
+        );
+    }
+}
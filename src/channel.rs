@@ -47,8 +47,13 @@ pub struct GuildMember {
 pub struct PartialUser {
     /// Username
     pub username: String,
-    /// Discriminator, the last four numbers
-    pub discriminator: String,
+    /// Display name chosen by the user, shown instead of the username
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_name: Option<String>,
+    /// Discriminator, the last four numbers. `"0"` or missing for users on the new username
+    /// system
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discriminator: Option<String>,
     /// User ID
     pub id: Snowflake,
     /// Avatar Hash
@@ -56,9 +61,35 @@ pub struct PartialUser {
 }
 
 impl PartialUser {
-    /// Discriminator numbers
-    pub fn nums(&self) -> usize {
-        self.discriminator.parse().unwrap()
+    /// Discriminator numbers, `None` for users on the new username system
+    pub fn nums(&self) -> Option<u16> {
+        discriminator(&self.discriminator)
+    }
+
+    /// Name to show for the user: the display name if set, otherwise the username
+    pub fn display_name(&self) -> &str {
+        self.global_name.as_deref().unwrap_or(&self.username)
+    }
+
+    /// Unique name of the user, `username#1234` for users with a discriminator and the username
+    /// otherwise
+    pub fn tag(&self) -> String {
+        tag(&self.username, self.nums())
+    }
+}
+
+/// Discriminator numbers, `None` when missing or `0`
+fn discriminator(discriminator: &Option<String>) -> Option<u16> {
+    discriminator
+        .as_deref()
+        .and_then(|d| d.parse().ok())
+        .filter(|&n| n != 0)
+}
+
+fn tag(username: &str, nums: Option<u16>) -> String {
+    match nums {
+        Some(nums) => format!("{username}#{nums:04}"),
+        None => username.to_string(),
     }
 }
 
@@ -69,8 +100,12 @@ pub struct User {
     pub id: Snowflake,
     /// User name
     pub username: String,
-    /// Discriminator, the four numbers
-    pub discriminator: String,
+    /// Display name chosen by the user, shown instead of the username
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_name: Option<String>,
+    /// Discriminator, the four numbers. `"0"` or missing for users on the new username system
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discriminator: Option<String>,
     /// Avatar Hash
     pub avatar: Option<String>,
     /// Is the user a bot
@@ -97,6 +132,24 @@ pub struct User {
     pub public_flags: Option<u64>,
 }
 
+impl User {
+    /// Discriminator numbers, `None` for users on the new username system
+    pub fn nums(&self) -> Option<u16> {
+        discriminator(&self.discriminator)
+    }
+
+    /// Name to show for the user: the display name if set, otherwise the username
+    pub fn display_name(&self) -> &str {
+        self.global_name.as_deref().unwrap_or(&self.username)
+    }
+
+    /// Unique name of the user, `username#1234` for users with a discriminator and the username
+    /// otherwise
+    pub fn tag(&self) -> String {
+        tag(&self.username, self.nums())
+    }
+}
+
 /// Partial Channel info
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PartialChannel {
@@ -202,3 +255,36 @@ pub struct ChannelMention {
     /// Channel Name
     pub name: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn old_and_new_usernames() {
+        let user: PartialUser = serde_json::from_value(json!({
+            "id": "7", "username": "user", "discriminator": "0042", "avatar": null
+        }))
+        .unwrap();
+        assert_eq!(user.nums(), Some(42));
+        assert_eq!(user.display_name(), "user");
+        assert_eq!(user.tag(), "user#0042");
+
+        let user: PartialUser = serde_json::from_value(json!({
+            "id": "7", "username": "user", "global_name": "User", "discriminator": "0",
+            "avatar": null
+        }))
+        .unwrap();
+        assert_eq!(user.nums(), None);
+        assert_eq!(user.display_name(), "User");
+        assert_eq!(user.tag(), "user");
+
+        let user: User =
+            serde_json::from_value(json!({ "id": "7", "username": "user", "avatar": null }))
+                .unwrap();
+        assert_eq!(user.nums(), None);
+        assert_eq!(user.display_name(), "user");
+    }
+}
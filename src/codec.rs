@@ -0,0 +1,416 @@
+//! IPC frame encoding & decoding, independent of any IO or async runtime
+//!
+//! Every message exchanged with Discord is a frame: a little endian `u32` opcode, a little endian
+//! `u32` payload length, and a JSON payload. [`FrameDecoder`] turns bytes into [`Frame`]s as they
+//! arrive, in chunks of any size, so it can be used by proxies, loggers or mock servers as well
+//! as by the client itself. With the `tokio-util` feature, [`FrameCodec`] provides the same as a
+//! `tokio_util::codec` `Decoder`/`Encoder`.
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Length of the opcode + length header at the start of every frame
+pub const HEADER_LEN: usize = 2 * std::mem::size_of::<u32>();
+
+/// Default maximum payload length accepted by decoders (16 MiB)
+pub const DEFAULT_MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Frame type
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum OpCode {
+    /// First frame sent by the client
+    HANDSHAKE = 0,
+    /// Command, response or event
+    FRAME = 1,
+    /// Connection is being closed
+    CLOSE = 2,
+    /// Keep alive request
+    PING = 3,
+    /// Keep alive response
+    PONG = 4,
+}
+
+impl TryFrom<u32> for OpCode {
+    type Error = u32;
+
+    fn try_from(value: u32) -> std::result::Result<Self, u32> {
+        match value {
+            0 => Ok(Self::HANDSHAKE),
+            1 => Ok(Self::FRAME),
+            2 => Ok(Self::CLOSE),
+            3 => Ok(Self::PING),
+            4 => Ok(Self::PONG),
+            _ => Err(value),
+        }
+    }
+}
+
+/// A single IPC frame
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Frame {
+    /// Frame type
+    pub opcode: OpCode,
+    /// JSON payload
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Create a frame with `message` serialized as the payload
+    pub fn new<M: Serialize>(opcode: OpCode, message: &M) -> Result<Self> {
+        Ok(Self {
+            opcode,
+            payload: serde_json::to_vec(message)?,
+        })
+    }
+
+    /// Deserialize the payload
+    pub fn json<'a, T: Deserialize<'a>>(&'a self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// Append the encoded frame to `dst`
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        dst.reserve(HEADER_LEN + self.payload.len());
+        dst.extend_from_slice(&u32::to_le_bytes(self.opcode as u32));
+        dst.extend_from_slice(&u32::to_le_bytes(self.payload.len() as u32));
+        dst.extend_from_slice(&self.payload);
+    }
+}
+
+/// Encode `message` into `buffer` as a single frame, replacing the previous contents
+pub fn encode_frame<M: Serialize>(buffer: &mut Vec<u8>, opcode: OpCode, message: &M) -> Result<()> {
+    const WIDTH: usize = std::mem::size_of::<u32>();
+    buffer.clear();
+    // Opcode
+    buffer.extend_from_slice(&u32::to_le_bytes(opcode as u32));
+    // Placeholder ([WIDTH..WIDTH*2]) for len
+    buffer.extend_from_slice(&[0u8; WIDTH]);
+    serde_json::to_writer(&mut *buffer, message)?;
+    // len = len - (Opcode + Len)
+    let len = buffer.len() - HEADER_LEN;
+    // Insert len
+    buffer[WIDTH..HEADER_LEN].copy_from_slice(&u32::to_le_bytes(len as u32));
+    trace!(
+        "-> {:?}",
+        std::str::from_utf8(&buffer[HEADER_LEN..]).unwrap_or("")
+    );
+    Ok(())
+}
+
+/// Split a frame header into the opcode and the length of the payload that follows
+pub(crate) fn decode_header(header: [u8; HEADER_LEN]) -> (u32, usize) {
+    let [a, b, c, d, e, f, g, h] = header;
+    (
+        u32::from_le_bytes([a, b, c, d]),
+        u32::from_le_bytes([e, f, g, h]) as usize,
+    )
+}
+
+/// Check the payload length of a decoded header against the limit of a decoder
+fn check_len(len: usize, max: usize) -> Result<()> {
+    if len > max {
+        Err(Error::FrameTooLarge { len, max })
+    } else {
+        Ok(())
+    }
+}
+
+/// Incremental frame decoder
+///
+/// Received bytes are either appended with [`extend`](Self::extend), or read directly into
+/// [`read_buf`](Self::read_buf) and committed with [`advance`](Self::advance). Partial frames are
+/// kept until the rest arrives, so the decoder can be fed in steps of any size.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    filled: usize,
+    max_len: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+impl FrameDecoder {
+    /// Create a decoder rejecting payloads longer than `max_len`
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buffer: vec![],
+            filled: 0,
+            max_len,
+        }
+    }
+
+    /// Header of the frame at the start of the buffer, if it has been received
+    fn header(&self) -> Option<(u32, usize)> {
+        if self.filled >= HEADER_LEN {
+            Some(decode_header(self.buffer[..HEADER_LEN].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    /// Number of bytes still missing from the frame at the start of the buffer
+    fn needed(&self) -> usize {
+        match self.header() {
+            None => HEADER_LEN - self.filled,
+            Some((_, len)) => (HEADER_LEN + len.min(self.max_len)).saturating_sub(self.filled),
+        }
+    }
+
+    /// Append received bytes
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.truncate(self.filled);
+        self.buffer.extend_from_slice(bytes);
+        self.filled = self.buffer.len();
+    }
+
+    /// Buffer to read the next bytes of the current frame into. Only empty if a complete frame
+    /// is waiting to be taken by [`next_frame`](Self::next_frame)
+    pub fn read_buf(&mut self) -> &mut [u8] {
+        let end = self.filled + self.needed();
+        self.buffer.resize(end, 0);
+        &mut self.buffer[self.filled..]
+    }
+
+    /// Commit `n` bytes written to the slice returned by [`read_buf`](Self::read_buf)
+    pub fn advance(&mut self, n: usize) {
+        self.filled += n;
+    }
+
+    /// Take the next frame out of the buffer, if it has been completely received
+    ///
+    /// A payload longer than the maximum is rejected with [`Error::FrameTooLarge`] as soon as the
+    /// header has been received, without buffering it. The decoder keeps returning the error,
+    /// since the rest of the stream can't be trusted. A frame with an unknown opcode is removed
+    /// from the buffer once complete, and reported with [`Error::UnknownOpcode`]. Decoding can
+    /// continue with the next frame.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        let Some((opcode, len)) = self.header() else {
+            return Ok(None);
+        };
+        check_len(len, self.max_len)?;
+        if self.filled < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let payload = self.buffer[HEADER_LEN..HEADER_LEN + len].to_vec();
+        self.buffer.drain(..HEADER_LEN + len);
+        self.filled -= HEADER_LEN + len;
+        trace!("<- {:?}", std::str::from_utf8(&payload).unwrap_or(""));
+        let opcode = OpCode::try_from(opcode).map_err(Error::UnknownOpcode)?;
+        Ok(Some(Frame { opcode, payload }))
+    }
+}
+
+/// `tokio_util::codec` implementation of the frame format
+#[cfg(feature = "tokio-util")]
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    max_len: usize,
+}
+
+#[cfg(feature = "tokio-util")]
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl FrameCodec {
+    /// Create a codec rejecting payloads longer than `max_len`
+    pub fn new(max_len: usize) -> Self {
+        Self { max_len }
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut tokio_util::bytes::BytesMut) -> Result<Option<Frame>> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let (opcode, len) = decode_header(src[..HEADER_LEN].try_into().unwrap());
+        check_len(len, self.max_len)?;
+        if src.len() < HEADER_LEN + len {
+            src.reserve(HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(HEADER_LEN + len);
+        let opcode = OpCode::try_from(opcode).map_err(Error::UnknownOpcode)?;
+        Ok(Some(Frame {
+            opcode,
+            payload: frame[HEADER_LEN..].to_vec(),
+        }))
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Encoder<Frame> for FrameCodec {
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: Frame, dst: &mut tokio_util::bytes::BytesMut) -> Result<()> {
+        dst.reserve(HEADER_LEN + item.payload.len());
+        dst.extend_from_slice(&u32::to_le_bytes(item.opcode as u32));
+        dst.extend_from_slice(&u32::to_le_bytes(item.payload.len() as u32));
+        dst.extend_from_slice(&item.payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn encoded(opcode: OpCode, message: Value) -> Vec<u8> {
+        let mut buffer = vec![];
+        encode_frame(&mut buffer, opcode, &message).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = encoded(OpCode::FRAME, json!({ "cmd": "GET_GUILDS" }));
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(&bytes);
+        let frame = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(frame.opcode, OpCode::FRAME);
+        assert_eq!(
+            frame.json::<Value>().unwrap(),
+            json!({ "cmd": "GET_GUILDS" })
+        );
+        assert_eq!(decoder.next_frame().unwrap(), None);
+
+        let mut reencoded = vec![];
+        frame.encode(&mut reencoded);
+        assert_eq!(reencoded, bytes);
+    }
+
+    #[test]
+    fn partial_reads() {
+        let mut bytes = encoded(OpCode::HANDSHAKE, json!({ "v": 1 }));
+        bytes.extend(encoded(OpCode::FRAME, json!({ "evt": "READY" })));
+        let mut decoder = FrameDecoder::default();
+        let mut frames = vec![];
+        let mut input = &bytes[..];
+        while !input.is_empty() {
+            // One byte at a time, through `read_buf`
+            let buf = decoder.read_buf();
+            buf[0] = input[0];
+            decoder.advance(1);
+            input = &input[1..];
+            frames.extend(decoder.next_frame().unwrap());
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].opcode, OpCode::HANDSHAKE);
+        assert_eq!(frames[1].json::<Value>().unwrap()["evt"], "READY");
+    }
+
+    #[test]
+    fn several_frames_in_one_read() {
+        let mut bytes = encoded(OpCode::PING, json!({}));
+        bytes.extend(encoded(OpCode::CLOSE, json!({ "code": 4000 })));
+        bytes.extend(&encoded(OpCode::FRAME, json!({}))[..5]);
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(&bytes);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().opcode, OpCode::PING);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().opcode, OpCode::CLOSE);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        assert_eq!(decoder.read_buf().len(), 3);
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut decoder = FrameDecoder::new(16);
+        decoder.extend(&1u32.to_le_bytes());
+        decoder.extend(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(Error::FrameTooLarge { len, max: 16 }) if len == u32::MAX as usize
+        ));
+        // Nothing is allocated for the body
+        assert!(decoder.read_buf().len() <= 16);
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut decoder = FrameDecoder::default();
+        decoder.extend(&9u32.to_le_bytes());
+        decoder.extend(&3u32.to_le_bytes());
+        decoder.extend(b"{}");
+        // Incomplete frames aren't reported yet
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(b" ");
+        decoder.extend(&encoded(OpCode::FRAME, json!({})));
+        assert!(matches!(decoder.next_frame(), Err(Error::UnknownOpcode(9))));
+        assert_eq!(decoder.next_frame().unwrap().unwrap().opcode, OpCode::FRAME);
+    }
+
+    proptest! {
+        #[test]
+        fn random_input(input in proptest::collection::vec(any::<u8>(), 0..512), max in 0usize..256) {
+            let mut decoder = FrameDecoder::new(max);
+            decoder.extend(&input);
+            loop {
+                match decoder.next_frame() {
+                    Ok(Some(frame)) => prop_assert!(frame.payload.len() <= max),
+                    Ok(None) | Err(Error::FrameTooLarge { .. }) => break,
+                    Err(Error::UnknownOpcode(opcode)) => prop_assert!(opcode > 4),
+                    Err(e) => panic!("Unexpected error: {e}"),
+                }
+            }
+            prop_assert!(decoder.read_buf().len() <= HEADER_LEN + max);
+        }
+
+        #[test]
+        fn random_chunks(
+            frames in proptest::collection::vec((0u32..5, proptest::collection::vec(any::<u8>(), 0..64)), 0..8),
+            chunk in 1usize..32,
+        ) {
+            let mut bytes = vec![];
+            for (opcode, payload) in &frames {
+                Frame { opcode: OpCode::try_from(*opcode).unwrap(), payload: payload.clone() }.encode(&mut bytes);
+            }
+            let mut decoder = FrameDecoder::default();
+            let mut decoded = vec![];
+            for chunk in bytes.chunks(chunk) {
+                decoder.extend(chunk);
+                while let Some(frame) = decoder.next_frame().unwrap() {
+                    decoded.push((frame.opcode as u32, frame.payload));
+                }
+            }
+            prop_assert_eq!(decoded, frames);
+        }
+    }
+
+    #[cfg(feature = "tokio-util")]
+    #[test]
+    fn tokio_util_codec() {
+        use tokio_util::{
+            bytes::BytesMut,
+            codec::{Decoder, Encoder},
+        };
+
+        let frame = Frame::new(OpCode::FRAME, &json!({ "cmd": "GET_GUILDS" })).unwrap();
+        let mut codec = FrameCodec::default();
+        let mut bytes = BytesMut::new();
+        codec.encode(frame.clone(), &mut bytes).unwrap();
+        let mut partial = bytes.split_to(6);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(bytes);
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(frame));
+        assert!(partial.is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 //! Commands sent to the Discord client
 
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use chrono::prelude::*;
 use serde::{de::Visitor, Deserialize, Serialize};
 
@@ -9,7 +11,9 @@ use crate::{
     discord::Snowflake,
     oauth::{Application, OauthScope},
     voice::{
-        CertifiedDevice, InputSettings, ModeSettings, OutputSettings, Pan, VoiceState, Volume,
+        CertifiedDevice, InputSettings, InputSettingsUpdate, Key, ModeSettings, OutputSettings,
+        OutputSettingsUpdate, Pan, Ping, UserVoicePatch, VoiceConnectionState, VoiceSettingsPatch,
+        VoiceState, Volume,
     },
 };
 
@@ -21,12 +25,61 @@ pub(crate) struct Subscribe {
     nonce: String,
 }
 
+/// Nonce for the next request. Discord echoes it in the response
+fn next_nonce() -> String {
+    static NONCE: AtomicU64 = AtomicU64::new(0);
+    NONCE.fetch_add(1, Ordering::Relaxed).to_string()
+}
+
+/// Identifies the response expected for a request: Discord echoes the `cmd` & `nonce` of the
+/// request in its response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseId {
+    /// Command name, e.g. `SET_ACTIVITY`
+    pub cmd: String,
+    /// Nonce sent with the command
+    pub nonce: Option<String>,
+}
+
+impl std::fmt::Display for ResponseId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.nonce {
+            Some(nonce) => write!(f, "{} (nonce {nonce})", self.cmd),
+            None => write!(f, "{} (no nonce)", self.cmd),
+        }
+    }
+}
+
+/// A message which Discord responds to
+pub(crate) trait Request: Serialize {
+    /// The response expected for this request
+    fn id(&self) -> ResponseId;
+}
+
+impl Request for Subscribe {
+    fn id(&self) -> ResponseId {
+        ResponseId {
+            cmd: self.cmd.to_string(),
+            nonce: Some(self.nonce.clone()),
+        }
+    }
+}
+
+impl Request for CommandWrapper {
+    fn id(&self) -> ResponseId {
+        ResponseId {
+            cmd: self.cmd.clone(),
+            nonce: Some(self.nonce.clone()),
+        }
+    }
+}
+
 impl Subscribe {
     pub fn sub(args: EventSubscribe) -> Self {
         Self {
             args,
             cmd: "SUBSCRIBE",
-            nonce: format!("{}", DateTime::<Local>::default()),
+            nonce: next_nonce(),
         }
     }
 
@@ -34,7 +87,7 @@ impl Subscribe {
         Self {
             args,
             cmd: "UNSUBSCRIBE",
-            nonce: format!("{}", DateTime::<Local>::default()),
+            nonce: next_nonce(),
         }
     }
 }
@@ -44,19 +97,23 @@ pub(crate) struct CommandWrapper {
     #[serde(flatten)]
     args: Command,
     nonce: String,
+    #[serde(skip)]
+    cmd: String,
 }
 
 impl CommandWrapper {
     pub fn new(args: Command) -> Self {
         Self {
+            cmd: args.name().to_string(),
             args,
-            nonce: format!("{}", DateTime::<Local>::default()),
+            nonce: next_nonce(),
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "cmd", content = "args")]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum Command {
     Authorize {
         scopes: Vec<OauthScope>,
@@ -82,15 +139,12 @@ pub(crate) enum Command {
     },
     SetUserVoiceSettings {
         user_id: Snowflake,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pan: Option<Pan>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        volume: Option<Volume>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        mute: Option<bool>,
+        #[serde(flatten)]
+        patch: UserVoicePatch,
     },
     SelectVoiceChannel {
-        channel_id: Snowflake,
+        /// Leaves the voice channel if `None`
+        channel_id: Option<Snowflake>,
         #[serde(skip_serializing_if = "Option::is_none")]
         timeout: Option<u64>,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -103,34 +157,18 @@ pub(crate) enum Command {
         timeout: Option<u64>,
     },
     GetVoiceSettings {},
-    SetVoiceSettings {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        input: Option<InputSettings>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        output: Option<OutputSettings>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        mode: Option<ModeSettings>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        automatic_gain_control: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        echo_cancellation: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        noise_suppression: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        qos: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        silence_warning: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        deaf: Option<bool>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        mute: Option<bool>,
+    SetVoiceSettings(VoiceSettingsPatch),
+    CaptureShortcut {
+        action: CaptureShortcutAction,
     },
     SetCertifiedDevices {
         devices: Vec<CertifiedDevice>,
     },
     SetActivity {
         pid: u32,
-        activity: Activity,
+        /// Clears the activity if not set
+        #[serde(skip_serializing_if = "Option::is_none")]
+        activity: Option<Activity>,
     },
     SendActivityJoinInvite {
         user_id: Snowflake,
@@ -140,6 +178,39 @@ pub(crate) enum Command {
     },
 }
 
+impl Command {
+    /// Name of the command, as sent to Discord
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Authorize { .. } => "AUTHORIZE",
+            Self::Authenticate { .. } => "AUTHENTICATE",
+            Self::GetGuilds {} => "GET_GUILDS",
+            Self::GetGuild { .. } => "GET_GUILD",
+            Self::GetChannels { .. } => "GET_CHANNELS",
+            Self::GetChannel { .. } => "GET_CHANNEL",
+            Self::SetUserVoiceSettings { .. } => "SET_USER_VOICE_SETTINGS",
+            Self::SelectVoiceChannel { .. } => "SELECT_VOICE_CHANNEL",
+            Self::GetSelectedVoiceChannel {} => "GET_SELECTED_VOICE_CHANNEL",
+            Self::SelectTextChannel { .. } => "SELECT_TEXT_CHANNEL",
+            Self::GetVoiceSettings {} => "GET_VOICE_SETTINGS",
+            Self::SetVoiceSettings(_) => "SET_VOICE_SETTINGS",
+            Self::CaptureShortcut { .. } => "CAPTURE_SHORTCUT",
+            Self::SetCertifiedDevices { .. } => "SET_CERTIFIED_DEVICES",
+            Self::SetActivity { .. } => "SET_ACTIVITY",
+            Self::SendActivityJoinInvite { .. } => "SEND_ACTIVITY_JOIN_INVITE",
+            Self::CloseActivityRequest { .. } => "CLOSE_ACTIVITY_REQUEST",
+        }
+    }
+}
+
+/// Start or stop recording a shortcut
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum CaptureShortcutAction {
+    Start,
+    Stop,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct Authorize {
     pub code: String,
@@ -157,7 +228,7 @@ pub(crate) struct Authenticate {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GetGuilds {
     /// List of partial guilds
-    guilds: Vec<PartialGuild>,
+    pub guilds: Vec<PartialGuild>,
 }
 
 /// Guild details
@@ -175,52 +246,109 @@ pub struct GetGuild {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GetChannels {
     /// List of partial channels
-    channels: Vec<PartialChannel>,
+    pub channels: Vec<PartialChannel>,
 }
 
-/// User Voice Settings
+/// Voice settings of another user, as updated by
+/// [`Client::set_user_voice_settings`](crate::Client::set_user_voice_settings)
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SetUserVoiceSettings {
     /// User ID
-    user_id: Snowflake,
+    pub user_id: Snowflake,
     /// Left/Right balance
     #[serde(skip_serializing_if = "Option::is_none")]
-    pan: Option<Pan>,
+    pub pan: Option<Pan>,
     /// User Volume
     #[serde(skip_serializing_if = "Option::is_none")]
-    volume: Option<Volume>,
+    pub volume: Option<Volume>,
     /// User Mute
     #[serde(skip_serializing_if = "Option::is_none")]
-    mute: Option<bool>,
+    pub mute: Option<bool>,
 }
 
-/// Unknown
+/// Current voice settings
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GetVoiceSettings {
+    /// Input device settings
     #[serde(skip_serializing_if = "Option::is_none")]
-    input: Option<InputSettings>,
+    pub input: Option<InputSettings>,
+    /// Output device settings
     #[serde(skip_serializing_if = "Option::is_none")]
-    output: Option<OutputSettings>,
+    pub output: Option<OutputSettings>,
+    /// Voice activity or push to talk settings
     #[serde(skip_serializing_if = "Option::is_none")]
-    mode: Option<ModeSettings>,
+    pub mode: Option<ModeSettings>,
+    /// Automatic gain control
     #[serde(skip_serializing_if = "Option::is_none")]
-    automatic_gain_control: Option<bool>,
+    pub automatic_gain_control: Option<bool>,
+    /// Echo cancellation
     #[serde(skip_serializing_if = "Option::is_none")]
-    echo_cancellation: Option<bool>,
+    pub echo_cancellation: Option<bool>,
+    /// Noise suppression
     #[serde(skip_serializing_if = "Option::is_none")]
-    noise_suppression: Option<bool>,
+    pub noise_suppression: Option<bool>,
+    /// Voice quality of service
     #[serde(skip_serializing_if = "Option::is_none")]
-    qos: Option<bool>,
+    pub qos: Option<bool>,
+    /// Warn when the microphone is silent
     #[serde(skip_serializing_if = "Option::is_none")]
-    silence_warning: Option<bool>,
+    pub silence_warning: Option<bool>,
+    /// Deafened
     #[serde(skip_serializing_if = "Option::is_none")]
-    deaf: Option<bool>,
+    pub deaf: Option<bool>,
+    /// Muted
     #[serde(skip_serializing_if = "Option::is_none")]
-    mute: Option<bool>,
+    pub mute: Option<bool>,
 }
 
-/// Unknown
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+impl GetVoiceSettings {
+    /// Apply the settings changed in an update. Device lists are kept if the update has none
+    pub fn apply(&mut self, update: &VoiceSettingsUpdate) {
+        fn set<T: Clone>(field: &mut Option<T>, update: &Option<T>) {
+            if update.is_some() {
+                field.clone_from(update);
+            }
+        }
+        if let Some(input) = &update.input {
+            let devices = self.input.take().map(|i| i.available_devices);
+            self.input = Some(InputSettings {
+                device_id: input.device_id.clone(),
+                volume: input.volume,
+                available_devices: input
+                    .available_devices
+                    .clone()
+                    .or(devices)
+                    .unwrap_or_default(),
+            });
+        }
+        if let Some(output) = &update.output {
+            let devices = self.output.take().map(|o| o.available_devices);
+            self.output = Some(OutputSettings {
+                device_id: output.device_id.clone(),
+                volume: output.volume,
+                available_devices: output
+                    .available_devices
+                    .clone()
+                    .or(devices)
+                    .unwrap_or_default(),
+            });
+        }
+        set(&mut self.mode, &update.mode);
+        set(
+            &mut self.automatic_gain_control,
+            &update.automatic_gain_control,
+        );
+        set(&mut self.echo_cancellation, &update.echo_cancellation);
+        set(&mut self.noise_suppression, &update.noise_suppression);
+        set(&mut self.qos, &update.qos);
+        set(&mut self.silence_warning, &update.silence_warning);
+        set(&mut self.deaf, &update.deaf);
+        set(&mut self.mute, &update.mute);
+    }
+}
+
+/// Voice settings to change, unset fields are left unchanged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SetVoiceSettings {
     /// Unknown
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -283,6 +411,13 @@ pub struct GetChannel {
     pub messages: Vec<Message>,
 }
 
+impl GetChannel {
+    /// IDs of the users in the channel
+    pub fn user_ids(&self) -> impl Iterator<Item = Snowflake> + '_ {
+        self.voice_states.iter().filter_map(VoiceState::user_id)
+    }
+}
+
 /// Unknown
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct CommandPayload {
@@ -374,6 +509,8 @@ pub enum EventSubscribe {
     VoiceSettingsUpdate,
     /// Connection state. Unknown
     VoiceConnectionStatus,
+    /// Keys pressed while a shortcut is being captured
+    CaptureShortcutChange,
     /// User starts speaking
     SpeakingStart {
         /// Channel to watch
@@ -410,7 +547,7 @@ pub enum EventSubscribe {
 }
 
 /// RPC Server configuration sent by Discord client
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct RPCServerConf {
     /// CDN prefix, e.g. `cdn.dicord.com`
     pub cdn_host: String,
@@ -432,11 +569,257 @@ pub(crate) struct Ready {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Error {
     /// Discord Error Code
-    pub code: u64,
+    pub code: RpcErrorCode,
     /// Discord Error Message
     pub message: String,
 }
 
+/// Error code sent by Discord in response to a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u64", into = "u64")]
+pub enum RpcErrorCode {
+    /// Unknown error
+    UnknownError,
+    /// The service is unavailable
+    ServiceUnavailable,
+    /// The transaction was aborted
+    TransactionAborted,
+    /// Invalid payload
+    InvalidPayload,
+    /// Invalid command name
+    InvalidCommand,
+    /// Invalid guild ID
+    InvalidGuild,
+    /// Invalid event name
+    InvalidEvent,
+    /// Invalid channel ID
+    InvalidChannel,
+    /// The access token doesn't grant the required scopes
+    InvalidPermissions,
+    /// Invalid client ID
+    InvalidClientId,
+    /// Invalid origin
+    InvalidOrigin,
+    /// Invalid access token
+    InvalidToken,
+    /// Invalid user ID
+    InvalidUser,
+    /// Invalid invite
+    InvalidInvite,
+    /// Invalid activity join request
+    InvalidActivityJoinRequest,
+    /// Invalid lobby
+    InvalidLobby,
+    /// Invalid lobby secret
+    InvalidLobbySecret,
+    /// Invalid entitlement
+    InvalidEntitlement,
+    /// Invalid gift code
+    InvalidGiftCode,
+    /// Generic OAuth2 error
+    OAuth2Error,
+    /// Selecting a channel timed out
+    SelectChannelTimedOut,
+    /// `GET_GUILD` timed out
+    GetGuildTimedOut,
+    /// The user is already in a voice channel, `force` is required to move them
+    SelectVoiceForceRequired,
+    /// A shortcut is already being captured
+    CaptureShortcutAlreadyListening,
+    /// Invalid activity secret
+    InvalidActivitySecret,
+    /// No activity is eligible for the request
+    NoEligibleActivity,
+    /// A purchase was cancelled
+    PurchaseCanceled,
+    /// A purchase failed
+    PurchaseError,
+    /// The application may not update this achievement
+    UnauthorizedForAchievement,
+    /// Too many requests were sent
+    RateLimited,
+    /// A code not known to this library
+    Unknown(u64),
+}
+
+impl RpcErrorCode {
+    /// Whether the same command could succeed if sent again later
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::UnknownError
+                | Self::ServiceUnavailable
+                | Self::TransactionAborted
+                | Self::SelectChannelTimedOut
+                | Self::GetGuildTimedOut
+                | Self::RateLimited
+        )
+    }
+
+    /// Whether the error is caused by the client's credentials or scopes, and authorizing again
+    /// may fix it
+    pub fn is_auth_error(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidPermissions
+                | Self::InvalidClientId
+                | Self::InvalidOrigin
+                | Self::InvalidToken
+                | Self::OAuth2Error
+        )
+    }
+}
+
+impl From<u64> for RpcErrorCode {
+    fn from(code: u64) -> Self {
+        match code {
+            1000 => Self::UnknownError,
+            1001 => Self::ServiceUnavailable,
+            1002 => Self::TransactionAborted,
+            4000 => Self::InvalidPayload,
+            4002 => Self::InvalidCommand,
+            4003 => Self::InvalidGuild,
+            4004 => Self::InvalidEvent,
+            4005 => Self::InvalidChannel,
+            4006 => Self::InvalidPermissions,
+            4007 => Self::InvalidClientId,
+            4008 => Self::InvalidOrigin,
+            4009 => Self::InvalidToken,
+            4010 => Self::InvalidUser,
+            4011 => Self::InvalidInvite,
+            4012 => Self::InvalidActivityJoinRequest,
+            4013 => Self::InvalidLobby,
+            4014 => Self::InvalidLobbySecret,
+            4015 => Self::InvalidEntitlement,
+            4016 => Self::InvalidGiftCode,
+            5000 => Self::OAuth2Error,
+            5001 => Self::SelectChannelTimedOut,
+            5002 => Self::GetGuildTimedOut,
+            5003 => Self::SelectVoiceForceRequired,
+            5004 => Self::CaptureShortcutAlreadyListening,
+            5005 => Self::InvalidActivitySecret,
+            5006 => Self::NoEligibleActivity,
+            5007 => Self::PurchaseCanceled,
+            5008 => Self::PurchaseError,
+            5009 => Self::UnauthorizedForAchievement,
+            5010 => Self::RateLimited,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl From<RpcErrorCode> for u64 {
+    fn from(code: RpcErrorCode) -> Self {
+        match code {
+            RpcErrorCode::UnknownError => 1000,
+            RpcErrorCode::ServiceUnavailable => 1001,
+            RpcErrorCode::TransactionAborted => 1002,
+            RpcErrorCode::InvalidPayload => 4000,
+            RpcErrorCode::InvalidCommand => 4002,
+            RpcErrorCode::InvalidGuild => 4003,
+            RpcErrorCode::InvalidEvent => 4004,
+            RpcErrorCode::InvalidChannel => 4005,
+            RpcErrorCode::InvalidPermissions => 4006,
+            RpcErrorCode::InvalidClientId => 4007,
+            RpcErrorCode::InvalidOrigin => 4008,
+            RpcErrorCode::InvalidToken => 4009,
+            RpcErrorCode::InvalidUser => 4010,
+            RpcErrorCode::InvalidInvite => 4011,
+            RpcErrorCode::InvalidActivityJoinRequest => 4012,
+            RpcErrorCode::InvalidLobby => 4013,
+            RpcErrorCode::InvalidLobbySecret => 4014,
+            RpcErrorCode::InvalidEntitlement => 4015,
+            RpcErrorCode::InvalidGiftCode => 4016,
+            RpcErrorCode::OAuth2Error => 5000,
+            RpcErrorCode::SelectChannelTimedOut => 5001,
+            RpcErrorCode::GetGuildTimedOut => 5002,
+            RpcErrorCode::SelectVoiceForceRequired => 5003,
+            RpcErrorCode::CaptureShortcutAlreadyListening => 5004,
+            RpcErrorCode::InvalidActivitySecret => 5005,
+            RpcErrorCode::NoEligibleActivity => 5006,
+            RpcErrorCode::PurchaseCanceled => 5007,
+            RpcErrorCode::PurchaseError => 5008,
+            RpcErrorCode::UnauthorizedForAchievement => 5009,
+            RpcErrorCode::RateLimited => 5010,
+            RpcErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+/// Payload of a CLOSE frame
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct Close {
+    pub code: CloseCode,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Reason Discord gave for closing the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u64", into = "u64")]
+pub enum CloseCode {
+    /// The connection was closed normally
+    Normal,
+    /// The client ID sent in the handshake is invalid
+    InvalidClientId,
+    /// The origin of the connection is not allowed
+    InvalidOrigin,
+    /// Too many requests were sent
+    RateLimited,
+    /// The access token was revoked
+    TokenRevoked,
+    /// The RPC version sent in the handshake is not supported
+    InvalidVersion,
+    /// The encoding requested in the handshake is not supported
+    InvalidEncoding,
+    /// A code not known to this library
+    Unknown(u64),
+}
+
+impl CloseCode {
+    /// Whether connecting again could succeed. False if the connection was rejected because of
+    /// the way this client is set up, e.g. an invalid client ID.
+    pub fn should_reconnect(&self) -> bool {
+        match self {
+            Self::Normal | Self::RateLimited | Self::TokenRevoked | Self::Unknown(_) => true,
+            Self::InvalidClientId
+            | Self::InvalidOrigin
+            | Self::InvalidVersion
+            | Self::InvalidEncoding => false,
+        }
+    }
+}
+
+impl From<u64> for CloseCode {
+    fn from(code: u64) -> Self {
+        match code {
+            1000 => Self::Normal,
+            4000 => Self::InvalidClientId,
+            4001 => Self::InvalidOrigin,
+            4002 => Self::RateLimited,
+            4003 => Self::TokenRevoked,
+            4004 => Self::InvalidVersion,
+            4005 => Self::InvalidEncoding,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl From<CloseCode> for u64 {
+    fn from(code: CloseCode) -> Self {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::InvalidClientId => 4000,
+            CloseCode::InvalidOrigin => 4001,
+            CloseCode::RateLimited => 4002,
+            CloseCode::TokenRevoked => 4003,
+            CloseCode::InvalidVersion => 4004,
+            CloseCode::InvalidEncoding => 4005,
+            CloseCode::Unknown(code) => code,
+        }
+    }
+}
+
 /// Unknown
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct GuildStatus {
@@ -477,9 +860,9 @@ pub struct VoiceChannelSelect {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VoiceSettingsUpdate {
     /// Unknown
-    pub input: Option<InputSettings>,
+    pub input: Option<InputSettingsUpdate>,
     /// Unknown
-    pub output: Option<OutputSettings>,
+    pub output: Option<OutputSettingsUpdate>,
     /// Unknown
     pub mode: Option<ModeSettings>,
     /// Unknown
@@ -498,19 +881,32 @@ pub struct VoiceSettingsUpdate {
     pub mute: Option<bool>,
 }
 
-/// Unknown
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Status of the connection to the voice server, see
+/// [`ConnectionMonitor`](crate::voice::ConnectionMonitor)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VoiceConnectionStatus {
-    /// Unknown
-    pub state: VoiceState,
-    /// Unknown
-    pub hostname: String,
-    /// Unknown
-    pub pings: Vec<u64>,
-    /// Unknown
-    pub average_ping: u64,
-    /// Unknown
-    pub last_ping: u64,
+    /// Connection state
+    pub state: VoiceConnectionState,
+    /// Voice server, if connecting or connected
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Recent pings
+    #[serde(default)]
+    pub pings: Vec<Ping>,
+    /// Average of the recent pings, in milliseconds
+    #[serde(default)]
+    pub average_ping: f64,
+    /// Latest ping, in milliseconds
+    #[serde(default)]
+    pub last_ping: Option<u64>,
+}
+
+/// Shortcut recorded while capturing, see
+/// [`Client::capture_shortcut`](crate::Client::capture_shortcut)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CaptureShortcutChange {
+    /// Keys held down
+    pub shortcut: Vec<Key>,
 }
 
 /// Unknown
@@ -553,6 +949,7 @@ pub struct Secret {
 /// Unknown
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ActivityJoinRequest {
+    /// Unknown
     pub user: PartialUser,
 }
 
@@ -581,6 +978,8 @@ pub enum EventResponse {
     VoiceSettingsUpdate(VoiceSettingsUpdate),
     /// Unknown
     VoiceConnectionStatus(VoiceConnectionStatus),
+    /// Shortcut recorded while capturing
+    CaptureShortcutChange(CaptureShortcutChange),
     /// User starts speaking
     SpeakingStart(SpeakingUpdate),
     /// User stops speaking
@@ -599,4 +998,49 @@ pub enum EventResponse {
     ActivitySpectate(Secret),
     /// User asks to join game
     ActivityJoinRequest(ActivityJoinRequest),
+    /// Event not known to this library
+    Unknown {
+        /// Event name
+        evt: String,
+        /// Event data
+        data: serde_json::Value,
+    },
+    /// Event that couldn't be parsed. Only returned if the client is lenient, see
+    /// [`ClientBuilder::lenient`](crate::ClientBuilder::lenient)
+    Malformed {
+        /// Event name
+        evt: String,
+        /// Event data
+        data: serde_json::Value,
+        /// Parsing error
+        error: String,
+    },
+}
+
+impl EventResponse {
+    /// Name of the event, as sent by Discord
+    pub fn evt(&self) -> &str {
+        match self {
+            Self::GuildStatus(_) => "GUILD_STATUS",
+            Self::GuildCreate(_) => "GUILD_CREATE",
+            Self::ChannelCreate(_) => "CHANNEL_CREATE",
+            Self::VoiceChannelSelect(_) => "VOICE_CHANNEL_SELECT",
+            Self::VoiceStateCreate(_) => "VOICE_STATE_CREATE",
+            Self::VoiceStateUpdate(_) => "VOICE_STATE_UPDATE",
+            Self::VoiceStateDelete(_) => "VOICE_STATE_DELETE",
+            Self::VoiceSettingsUpdate(_) => "VOICE_SETTINGS_UPDATE",
+            Self::VoiceConnectionStatus(_) => "VOICE_CONNECTION_STATUS",
+            Self::CaptureShortcutChange(_) => "CAPTURE_SHORTCUT_CHANGE",
+            Self::SpeakingStart(_) => "SPEAKING_START",
+            Self::SpeakingStop(_) => "SPEAKING_STOP",
+            Self::MessageCreate(_) => "MESSAGE_CREATE",
+            Self::MessageUpdate(_) => "MESSAGE_UPDATE",
+            Self::MessageDelete(_) => "MESSAGE_DELETE",
+            Self::NotificationCreate(_) => "NOTIFICATION_CREATE",
+            Self::ActivityJoin(_) => "ACTIVITY_JOIN",
+            Self::ActivitySpectate(_) => "ACTIVITY_SPECTATE",
+            Self::ActivityJoinRequest(_) => "ACTIVITY_JOIN_REQUEST",
+            Self::Unknown { evt, .. } | Self::Malformed { evt, .. } => evt,
+        }
+    }
 }
@@ -15,10 +15,15 @@ pub struct UnixTimestamp(u64);
 
 impl Default for UnixTimestamp {
     fn default() -> Self {
+        std::time::SystemTime::now().into()
+    }
+}
+
+impl From<std::time::SystemTime> for UnixTimestamp {
+    fn from(time: std::time::SystemTime) -> Self {
         Self(
-            std::time::SystemTime::now()
-                .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                .unwrap()
+            time.duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
                 .as_millis() as u64,
         )
     }
@@ -26,6 +31,7 @@ impl Default for UnixTimestamp {
 
 impl UnixTimestamp {
     /// Convert to chrono::DateTime
+    #[allow(deprecated)]
     pub fn as_chrono(&self) -> NaiveDateTime {
         NaiveDateTime::from_timestamp_opt(self.0 as i64, 0).unwrap()
     }
@@ -44,6 +50,20 @@ impl Snowflake {
     }
 }
 
+impl From<u64> for Snowflake {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+impl std::str::FromStr for Snowflake {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
 impl Display for Snowflake {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
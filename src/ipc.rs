@@ -1,98 +1,239 @@
 use std::{
     collections::VecDeque,
+    future::{poll_fn, Future},
+    io,
+    pin::{pin, Pin},
+    task::Poll,
     time::{Duration, Instant},
 };
 
-use log::*;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use serde_json::Value;
 
 use crate::{
     channel::PartialUser,
+    codec::{self, Frame, FrameDecoder, OpCode, HEADER_LEN},
     command::{
-        Authenticate, Authorize, Command, CommandWrapper, Empty, EventResponse, RPCServerConf,
+        Authenticate, Authorize, Close, CloseCode, Command, CommandWrapper, Empty, EventResponse,
+        RPCServerConf, Ready, Request, ResponseId,
     },
     discord::Snowflake,
-    oauth::{
-        GrantType, Secret, TokenRefresh, TokenRefreshServer, TokenReq, TokenReqServer, TokenRes,
-    },
-    payload::{self, OutPayload},
-    ClientBuilder, Connection, Error, Result, SecretType,
+    metrics::{self, span_record},
+    oauth::Secret,
+    payload::{self, OutPayload, ParseOptions},
+    record::{Direction, Recorder},
+    transport, ClientBuilder, Connection, Error, Result,
 };
 
 pub struct Framed<C> {
     client_id: u64,
     connection: C,
+    /// Frame being written, `buffer[written..]` hasn't been written yet
     buffer: Vec<u8>,
-    event_queue: VecDeque<EventResponse>,
+    written: usize,
+    decoder: FrameDecoder,
+    event_queue: VecDeque<RawEvent>,
+    options: ParseOptions,
+    /// Response expected for the last request
+    pending: Option<ResponseId>,
+    recorder: Option<Recorder>,
+    /// Responses to requests that timed out or were cancelled, skipped if they arrive later
+    abandoned: VecDeque<ResponseId>,
+    timeout: Option<Duration>,
+    next_timeout: Option<Duration>,
 
     auth: Option<Secret>,
     pub(crate) config: RPCServerConf,
 }
 
-#[repr(u32)]
-pub enum OpCode {
-    HANDSHAKE = 0,
-    FRAME = 1,
-    CLOSE = 2,
-    PING = 3,
-    PONG = 4,
+/// Maximum number of abandoned requests remembered
+const MAX_ABANDONED: usize = 16;
+
+/// An event, along with its raw `data` if the client keeps it
+pub(crate) type RawEvent = (EventResponse, Option<Value>);
+
+/// Interpret a received frame
+pub(crate) fn decode_frame<M: DeserializeOwned>(
+    frame: Frame,
+    options: ParseOptions,
+    expected: Option<&ResponseId>,
+) -> Result<OutPayload<M>> {
+    match frame.opcode {
+        OpCode::FRAME => {
+            let payload = payload::parse_response(&frame.payload, options, expected);
+            if let Ok(OutPayload::Event(_event, _)) = &payload {
+                metrics::event();
+                span_record!("evt", _event.evt());
+            }
+            payload
+        }
+        OpCode::CLOSE => {
+            let close: Close = serde_json::from_slice(&frame.payload).unwrap_or_else(|_| Close {
+                code: CloseCode::Unknown(0),
+                message: String::from_utf8_lossy(&frame.payload).into_owned(),
+            });
+            Err(Error::Closed {
+                code: close.code,
+                message: close.message,
+            })
+        }
+        OpCode::HANDSHAKE | OpCode::PING | OpCode::PONG => Err(Error::UnexpectedResponse),
+    }
+}
+
+/// Run `future`, failing with [`Error::Timeout`] if it doesn't complete within `duration`
+pub(crate) async fn timeout<T>(
+    duration: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(duration) = duration else {
+        return future.await;
+    };
+    let mut future = pin!(future);
+    let mut sleep = pin!(transport::sleep(duration));
+    poll_fn(|cx| {
+        if let Poll::Ready(res) = future.as_mut().poll(cx) {
+            return Poll::Ready(res);
+        }
+        sleep.as_mut().poll(cx).map(|()| Err(Error::Timeout))
+    })
+    .await
+}
+
+/// Handle a payload received while waiting for a command response. Events are queued so that
+/// they can be returned by a later call to `event`.
+pub(crate) fn route_response<M>(
+    payload: OutPayload<M>,
+    event_queue: &mut VecDeque<RawEvent>,
+) -> Option<Result<M>> {
+    match payload {
+        OutPayload::Error(e) => Some(Err(Error::Discord(e))),
+        OutPayload::Ready(_) => Some(Err(Error::UnexpectedEvent)),
+        OutPayload::Event(e, raw) => {
+            event_queue.push_back((e, raw));
+            None
+        }
+        OutPayload::CommandResponse(m) => Some(Ok(m)),
+    }
+}
+
+/// Handle a payload received while waiting for an event
+pub(crate) fn route_event(payload: OutPayload<()>) -> Result<RawEvent> {
+    match payload {
+        OutPayload::Error(e) => Err(Error::Discord(e)),
+        OutPayload::Ready(_) => Err(Error::UnexpectedEvent),
+        OutPayload::Event(e, raw) => Ok((e, raw)),
+        OutPayload::CommandResponse(_m) => Err(Error::UnexpectedResponse),
+    }
+}
+
+/// Handle the first payload received after the handshake
+pub(crate) fn route_ready(payload: OutPayload<Empty>) -> Result<Ready> {
+    match payload {
+        OutPayload::Ready(ready) => Ok(ready),
+        _ => Err(Error::UnexpectedEvent),
+    }
+}
+
+/// Load the OAuth state for a new connection. The flag is set if a refresh token had already
+/// been saved, in which case refreshing should be attempted before a full authentication.
+pub(crate) async fn load_secret(config: ClientBuilder) -> Result<Option<(Secret, bool)>> {
+    if let Some(secret_val) = config.secret {
+        let refresh_token = config.save_refresh.load().await.ok().and_then(|f| f);
+        let has_refresh = refresh_token.is_some();
+        let secret = Secret::new(
+            secret_val,
+            Instant::now() - Duration::from_secs(1),
+            config.save_refresh,
+            config.scopes,
+        )
+        .await?;
+        Ok(Some((secret, has_refresh)))
+    } else {
+        Ok(None)
+    }
+}
+
+impl<C> Framed<C> {
+    /// Drop the queued events matching `f`
+    pub(crate) fn discard_events(&mut self, mut f: impl FnMut(&EventResponse) -> bool) {
+        self.event_queue.retain(|(e, _)| !f(e));
+    }
+
+    /// Put events back in front of the queue, to be returned by the next calls to `event`
+    pub(crate) fn requeue(&mut self, events: Vec<RawEvent>) {
+        for e in events.into_iter().rev() {
+            self.event_queue.push_front(e);
+        }
+    }
 }
 
 impl<C: Connection> Framed<C> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(client_id = config.client_id))
+    )]
     pub(crate) async fn connect(
-        config: ClientBuilder,
+        mut config: ClientBuilder,
         connection: C,
     ) -> Result<(Self, PartialUser)> {
         let mut client = Self {
             client_id: config.client_id,
             connection,
             buffer: vec![],
+            written: 0,
+            decoder: FrameDecoder::new(config.max_frame_len),
             event_queue: VecDeque::new(),
+            options: config.parse_options(),
+            pending: None,
+            recorder: config.recorder.take(),
+            abandoned: VecDeque::new(),
+            timeout: config.timeout,
+            next_timeout: None,
 
             auth: None,
-            config: RPCServerConf {
-                cdn_host: "".into(),
-                api_endpoint: "".into(),
-                environment: "".into(),
-            },
+            config: RPCServerConf::default(),
         };
-        client
-            .send_message(
-                OpCode::HANDSHAKE,
-                HandshakeRequest {
-                    v: 1,
-                    client_id: Snowflake(client.client_id),
-                },
-            )
-            .await?;
-        if let OutPayload::Ready(a) = client.recv::<Empty>().await? {
-            client.config = a.config;
-            if let Some(secret_val) = config.secret {
-                let refresh_token = config.save_refresh.load().await.ok().and_then(|f| f);
-                let has_refresh = refresh_token.is_some();
-                client.auth = Some(
-                    Secret::new(
-                        secret_val,
-                        Instant::now() - Duration::from_secs(1),
-                        config.save_refresh,
-                        config.scopes,
-                    )
-                    .await?,
-                );
-                if has_refresh {
-                    if client.refresh_auth().await.is_err() {
-                        client.authenticate().await?;
-                    }
-                } else {
-                    client.authenticate().await?;
-                }
+        let user = metrics::count(
+            async {
+                let user = client.handshake().await?;
+                client.login(config).await?;
+                Ok(user)
             }
+            .await,
+        )?;
+        metrics::connected();
+        Ok((client, user))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(bytes_sent)))]
+    async fn handshake(&mut self) -> Result<PartialUser> {
+        self.send_message(
+            OpCode::HANDSHAKE,
+            HandshakeRequest {
+                v: 1,
+                client_id: Snowflake(self.client_id),
+            },
+        )
+        .await?;
+        let ready = route_ready(timeout(self.timeout, self.recv()).await?)?;
+        self.config = ready.config;
+        Ok(ready.user)
+    }
 
-            Ok((client, a.user))
-        } else {
-            Err(Error::UnexpectedEvent)
+    /// Authenticate if a secret is configured
+    async fn login(&mut self, config: ClientBuilder) -> Result<()> {
+        if let Some((secret, has_refresh)) = load_secret(config).await? {
+            self.auth = Some(secret);
+            if has_refresh {
+                if self.refresh_auth().await.is_err() {
+                    self.authenticate().await?;
+                }
+            } else {
+                self.authenticate().await?;
+            }
         }
+        Ok(())
     }
 
     pub(crate) async fn send_message<M: Serialize>(
@@ -100,129 +241,259 @@ impl<C: Connection> Framed<C> {
         opcode: OpCode,
         message: M,
     ) -> Result<&mut Self> {
-        const WIDTH: usize = std::mem::size_of::<u32>();
-        self.buffer.clear();
-        // Opcode
-        self.buffer
-            .extend_from_slice(&u32::to_le_bytes(opcode as u32));
-        // Placeholder ([WIDTH..WIDTH*2]) for len
-        self.buffer.extend_from_slice(&[0u8; WIDTH]);
-        serde_json::to_writer(&mut self.buffer, &message)?;
-        // len = len - (Opcode + Len)
-        let len = self.buffer.len() - WIDTH * 2;
-        // Insert len
-        self.buffer[WIDTH..WIDTH * 2].copy_from_slice(&u32::to_le_bytes(len as u32));
-        trace!(
-            "-> {:?}",
-            std::str::from_utf8(&self.buffer[WIDTH * 2..]).unwrap_or("")
-        );
-        self.connection.write_all(&self.buffer).await?;
+        self.write_buffer().await?;
+        if let Err(e) = codec::encode_frame(&mut self.buffer, opcode, &message) {
+            self.buffer.clear();
+            return Err(e);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::Sent, opcode as u32, &self.buffer[HEADER_LEN..]);
+        }
+        span_record!("bytes_sent", self.buffer.len());
+        self.written = 0;
+        self.write_buffer().await?;
         Ok(self)
     }
 
-    pub(crate) async fn send_command(&mut self, command: Command) -> Result<&mut Self> {
-        self.refresh_auth().await?;
-        self.send_message(OpCode::FRAME, CommandWrapper::new(command))
-            .await?;
-        Ok(self)
+    /// Send a request, checking the next response against it
+    pub(crate) async fn send_request<R: Request>(&mut self, request: R) -> Result<&mut Self> {
+        if let Some(id) = self.pending.replace(request.id()) {
+            self.abandon(id);
+        }
+        self.send_message(OpCode::FRAME, request).await
     }
 
+    /// Finish writing the frame in `buffer`. Progress is kept in `written`, so that a write
+    /// cancelled part way through a frame is resumed by the next call
+    async fn write_buffer(&mut self) -> Result<()> {
+        while self.written < self.buffer.len() {
+            let buf = &self.buffer[self.written..];
+            let n = poll_fn(|cx| Pin::new(&mut self.connection).poll_write(cx, buf)).await?;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            self.written += n;
+        }
+        poll_fn(|cx| Pin::new(&mut self.connection).poll_flush(cx)).await?;
+        Ok(())
+    }
+
+    /// Send a command and wait for its response
+    pub(crate) async fn command<M: DeserializeOwned>(&mut self, command: Command) -> Result<M> {
+        self.request(CommandWrapper::new(command)).await
+    }
+
+    /// Refresh the access token if it expired, then send a request and wait for its response
+    pub(crate) async fn request<R: Request, M: DeserializeOwned>(
+        &mut self,
+        request: R,
+    ) -> Result<M> {
+        metrics::count(
+            async {
+                self.refresh_auth().await?;
+                self.send_and_wait(request).await
+            }
+            .await,
+        )
+    }
+
+    /// Send a request and wait for its response
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "command",
+            skip_all,
+            fields(cmd, nonce, bytes_sent, bytes_received, latency_ms, code)
+        )
+    )]
+    async fn send_and_wait<R: Request, M: DeserializeOwned>(&mut self, request: R) -> Result<M> {
+        metrics::command();
+        #[cfg(feature = "tracing")]
+        let start = {
+            let id = request.id();
+            span_record!("cmd", id.cmd.as_str());
+            span_record!("nonce", id.nonce.as_deref());
+            Instant::now()
+        };
+        let res = match self.send_request(request).await {
+            Ok(_) => self.response().await,
+            Err(e) => {
+                self.next_timeout = None;
+                Err(e)
+            }
+        };
+        #[cfg(feature = "tracing")]
+        {
+            span_record!("latency_ms", start.elapsed().as_millis() as u64);
+            if let Err(e) = &res {
+                span_record!("code", e.rpc_code().map(u64::from));
+            }
+        }
+        res
+    }
+
+    /// Receive the next payload. Cancelling this doesn't lose data: partial frames stay in the
+    /// decoder, and a partially written PONG is finished by the next read or write
     pub(crate) async fn recv<M: DeserializeOwned>(&mut self) -> Result<OutPayload<M>> {
-        let mut buf = [0u8; 4];
-        self.connection.read_exact(&mut buf).await?;
-        let ty = u32::from_le_bytes(buf);
-        self.connection.read_exact(&mut buf).await?;
-        let len = u32::from_le_bytes(buf);
-        if ty == OpCode::FRAME as u32 {
-            let mut buf = vec![0u8; len as usize];
-            self.connection.read_exact(&mut buf).await?;
-            trace!("<- {:?}", std::str::from_utf8(&buf).unwrap_or(""));
-            Ok(payload::parse_response(&buf)?)
-        } else if ty == OpCode::CLOSE as u32 {
-            let mut buf = vec![0u8; len as usize];
-            self.connection.read_exact(&mut buf).await?;
-            trace!("<- {:?}", std::str::from_utf8(&buf).unwrap_or(""));
-            Err(Error::PipeClosed)
-        } else if ty == OpCode::PING as u32 {
-            todo!("Ping")
-        } else if ty == OpCode::PONG as u32 {
-            todo!("Pong")
-        } else {
-            Err(Error::PipeClosed)
+        self.write_buffer().await?;
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                span_record!("bytes_received", frame.payload.len());
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record(Direction::Received, frame.opcode as u32, &frame.payload);
+                }
+                match frame.opcode {
+                    OpCode::PING => {
+                        // Any earlier frame has been written at the start of `recv`
+                        self.buffer.clear();
+                        Frame {
+                            opcode: OpCode::PONG,
+                            ..frame
+                        }
+                        .encode(&mut self.buffer);
+                        if let Some(recorder) = &mut self.recorder {
+                            recorder.record(
+                                Direction::Sent,
+                                OpCode::PONG as u32,
+                                &self.buffer[HEADER_LEN..],
+                            );
+                        }
+                        self.written = 0;
+                        self.write_buffer().await?;
+                    }
+                    OpCode::PONG => (),
+                    OpCode::FRAME if self.skip_abandoned(&frame.payload)? => (),
+                    _ => return decode_frame(frame, self.options, self.pending.as_ref()),
+                }
+                continue;
+            }
+            let buf = self.decoder.read_buf();
+            let n = poll_fn(|cx| Pin::new(&mut self.connection).poll_read(cx, buf)).await?;
+            if n == 0 {
+                return Err(Error::PipeClosed);
+            }
+            self.decoder.advance(n);
         }
     }
 
+    /// Wait for the response to the last request. If this times out, is cancelled or gets
+    /// another response, the response is skipped when it arrives later
     pub(crate) async fn response<M: DeserializeOwned>(&mut self) -> Result<M> {
+        let duration = self.next_timeout.take().or(self.timeout);
+        let res = timeout(duration, self.wait_response()).await;
+        if let Some(id) = self.pending.take() {
+            if matches!(res, Err(Error::Timeout | Error::ResponseMismatch { .. })) {
+                self.abandon(id);
+            }
+        }
+        res
+    }
+
+    async fn wait_response<M: DeserializeOwned>(&mut self) -> Result<M> {
         loop {
-            match self.recv().await? {
-                OutPayload::Error(e) => return Err(Error::Discord(e)),
-                OutPayload::Ready(_) => return Err(Error::UnexpectedEvent),
-                OutPayload::Event(e) => self.event_queue.push_back(e),
-                OutPayload::CommandResponse(m) => return Ok(m),
+            if let Some(res) = route_response(self.recv().await?, &mut self.event_queue) {
+                return res;
             }
         }
     }
 
-    pub(crate) async fn event(&mut self) -> Result<EventResponse> {
-        self.refresh_auth().await?;
-        if let Some(e) = self.event_queue.pop_front() {
-            Ok(e)
-        } else {
-            match self.recv::<()>().await? {
-                OutPayload::Error(e) => return Err(Error::Discord(e)),
-                OutPayload::Ready(_) => return Err(Error::UnexpectedEvent),
-                OutPayload::Event(e) => Ok(e),
-                OutPayload::CommandResponse(_m) => Err(Error::UnexpectedResponse),
+    /// Check whether a payload is the late response to an abandoned request, forgetting the
+    /// request if it is
+    fn skip_abandoned(&mut self, payload: &[u8]) -> Result<bool> {
+        if self.abandoned.is_empty() {
+            return Ok(false);
+        }
+        let Some(id) = payload::response_id(payload)? else {
+            return Ok(false);
+        };
+        match self.abandoned.iter().position(|a| a == &id) {
+            Some(i) => {
+                self.abandoned.remove(i);
+                Ok(true)
             }
+            None => Ok(false),
+        }
+    }
+
+    fn abandon(&mut self, id: ResponseId) {
+        if self.abandoned.len() == MAX_ABANDONED {
+            self.abandoned.pop_front();
         }
+        self.abandoned.push_back(id);
     }
 
+    /// Set the timeout of the next response
+    pub(crate) fn set_next_timeout(&mut self, duration: Duration) {
+        self.next_timeout = Some(duration);
+    }
+
+    /// Wait at least `duration` for the next response, unless responses are waited for
+    /// indefinitely
+    pub(crate) fn extend_next_timeout(&mut self, duration: Duration) {
+        if let Some(timeout) = self.next_timeout.or(self.timeout) {
+            self.next_timeout = Some(timeout.max(duration));
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(evt)))]
+    pub(crate) async fn event(&mut self) -> Result<RawEvent> {
+        // A request is still pending if waiting for its response was cancelled
+        if let Some(id) = self.pending.take() {
+            self.abandon(id);
+        }
+        metrics::count(
+            async {
+                self.refresh_auth().await?;
+                if let Some(e) = self.event_queue.pop_front() {
+                    span_record!("evt", e.0.evt());
+                    Ok(e)
+                } else {
+                    route_event(self.recv().await?)
+                }
+            }
+            .await,
+        )
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "authorize", skip_all))]
     pub(crate) async fn authenticate(&mut self) -> Result<()> {
         if let Some(mut auth) = self.auth.take() {
-            self.send_message(
-                OpCode::FRAME,
-                CommandWrapper::new(Command::Authorize {
+            let token: Authorize = self
+                .send_and_wait(CommandWrapper::new(Command::Authorize {
                     scopes: auth.scopes.clone(),
                     client_id: Snowflake(self.client_id),
                     rpc_token: None,
-                }),
-            )
-            .await?;
-            let token: Authorize = self.response().await?;
+                }))
+                .await?;
             let access_token = auth
                 .authorization_token(self.client_id, &self.config, &token.code)
                 .await?;
-            self.send_message(
-                OpCode::FRAME,
-                CommandWrapper::new(Command::Authenticate { access_token }),
-            )
-            .await?;
-            let _: Authenticate = self.response().await?;
+            let _: Authenticate = self
+                .send_and_wait(CommandWrapper::new(Command::Authenticate { access_token }))
+                .await?;
             self.auth = Some(auth);
         }
         Ok(())
     }
 
-    pub(crate) async fn refresh_auth(&mut self) -> Result<()> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "refresh_token", skip_all)
+    )]
+    async fn refresh_auth(&mut self) -> Result<()> {
         if let Some(auth) = self.auth.as_mut() {
             if let Some(access_token) = auth
                 .refresh_token(self.client_id, &self.config)
                 .await?
                 .map(|s| s.to_string())
             {
-                self.send_message(
-                    OpCode::FRAME,
-                    CommandWrapper::new(Command::Authenticate { access_token }),
-                )
-                .await?;
-                loop {
-                    match self.recv::<Authenticate>().await? {
-                        OutPayload::Error(e) => return Err(Error::Discord(e)),
-                        OutPayload::Ready(_) => return Err(Error::UnexpectedEvent),
-                        OutPayload::Event(e) => self.event_queue.push_back(e),
-                        OutPayload::CommandResponse(_) => break,
-                    }
-                }
+                // The timeout set for the next command doesn't apply to refreshing
+                let next_timeout = self.next_timeout.take();
+                let res: Result<Authenticate> = self
+                    .send_and_wait(CommandWrapper::new(Command::Authenticate { access_token }))
+                    .await;
+                self.next_timeout = next_timeout;
+                res?;
             }
         }
         Ok(())
@@ -230,7 +501,209 @@ impl<C: Connection> Framed<C> {
 }
 
 #[derive(Debug, Serialize)]
-struct HandshakeRequest {
-    v: usize,
-    client_id: Snowflake,
+pub(crate) struct HandshakeRequest {
+    pub v: usize,
+    pub client_id: Snowflake,
+}
+
+#[cfg(all(test, unix, feature = "tokio"))]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::{
+        codec::OpCode,
+        command::CloseCode,
+        discord::Snowflake,
+        mock::{tokio_socket, MockDiscord},
+        transport::TokioIo,
+        Client, Error,
+    };
+
+    async fn client(
+        socket: std::os::unix::net::UnixStream,
+    ) -> Client<TokioIo<tokio::net::UnixStream>> {
+        Client::new(42)
+            .max_frame_len(1024)
+            .connect_with(tokio_socket(socket))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn keep_alive_and_unknown_opcodes() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.write_frame(OpCode::PING, json!({ "nonce": 1 }));
+            assert_eq!(
+                discord.read_frame(),
+                (OpCode::PONG as u32, json!({ "nonce": 1 }))
+            );
+            discord.write_raw(&[&9u32.to_le_bytes()[..], &2u32.to_le_bytes(), b"{}"].concat());
+            discord.dispatch("VOICE_CHANNEL_SELECT", json!({ "channel_id": null }));
+            discord.write_raw(&[&1u32.to_le_bytes()[..], &u32::MAX.to_le_bytes()].concat());
+        });
+        let mut client = client(socket).await;
+        assert!(matches!(client.event().await, Err(Error::UnknownOpcode(9))));
+        assert!(client.event().await.is_ok());
+        assert!(matches!(
+            client.event().await,
+            Err(Error::FrameTooLarge { max: 1024, .. })
+        ));
+        discord.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn close_reasons() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.read_frame();
+            discord.write_frame(
+                OpCode::CLOSE,
+                json!({ "code": 4000, "message": "Invalid Client ID" }),
+            );
+        });
+        let err = Client::new(42)
+            .connect_with(tokio_socket(socket))
+            .await
+            .err();
+        assert!(matches!(
+            err,
+            Some(Error::Closed { code: CloseCode::InvalidClientId, ref message }) if message == "Invalid Client ID"
+        ));
+        assert!(!CloseCode::InvalidClientId.should_reconnect());
+        discord.join().unwrap();
+
+        let (socket, discord) = MockDiscord::spawn(|mut discord| discord.handshake());
+        let mut client = client(socket).await;
+        discord.join().unwrap();
+        assert!(matches!(client.event().await, Err(Error::PipeClosed)));
+    }
+
+    #[tokio::test]
+    async fn mismatched_response() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.expect_command("SET_ACTIVITY");
+            discord.respond("GET_SELECTED_VOICE_CHANNEL", json!(null));
+            // The real response arrives late, and is skipped by the next command
+            discord.respond("SET_ACTIVITY", json!({ "name": "test", "type": 0 }));
+            discord.expect_command("GET_SELECTED_VOICE_CHANNEL");
+            discord.respond("GET_SELECTED_VOICE_CHANNEL", json!(null));
+        });
+        let mut client = client(socket).await;
+        let Err(Error::ResponseMismatch { expected, got }) = client.set_activity("Testing").await
+        else {
+            panic!("Expected a mismatch");
+        };
+        assert_eq!(expected.cmd, "SET_ACTIVITY");
+        assert_eq!(got.cmd, "GET_SELECTED_VOICE_CHANNEL");
+        assert_eq!(expected.nonce, got.nonce);
+        assert!(client.get_selected_channel().await.unwrap().is_none());
+        discord.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn timeouts_and_cancellation() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            let nonces: Vec<_> = (0..3)
+                .map(|_| discord.expect_command("SET_ACTIVITY")["nonce"].clone())
+                .collect();
+            for nonce in nonces {
+                discord.write_frame(
+                    OpCode::FRAME,
+                    json!({
+                        "cmd": "SET_ACTIVITY",
+                        "evt": null,
+                        "data": { "name": "test", "type": 0 },
+                        "nonce": nonce
+                    }),
+                );
+            }
+        });
+        let mut client = client(socket).await;
+        tokio::select! {
+            _ = client.set_activity("Cancelled") => panic!("Discord doesn't respond yet"),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => (),
+        }
+        assert!(matches!(
+            client
+                .timeout(Duration::from_millis(50))
+                .set_activity("Timed out")
+                .await,
+            Err(Error::Timeout)
+        ));
+        client.set_activity("Testing").await.unwrap();
+        discord.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn late_responses_while_waiting_for_events() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            let nonces: Vec<_> = (0..2)
+                .map(|_| discord.expect_command("SET_ACTIVITY")["nonce"].clone())
+                .collect();
+            // Let the client give up on both commands and wait for an event
+            std::thread::sleep(Duration::from_millis(100));
+            for nonce in nonces {
+                discord.write_frame(
+                    OpCode::FRAME,
+                    json!({
+                        "cmd": "SET_ACTIVITY",
+                        "evt": null,
+                        "data": { "name": "test", "type": 0 },
+                        "nonce": nonce
+                    }),
+                );
+            }
+            discord.dispatch("VOICE_CHANNEL_SELECT", json!({ "channel_id": null }));
+            discord.expect_command("SET_ACTIVITY");
+            discord.respond("SET_ACTIVITY", json!({ "name": "test", "type": 0 }));
+        });
+        let mut client = client(socket).await;
+        assert!(matches!(
+            client
+                .timeout(Duration::from_millis(20))
+                .set_activity("Timed out")
+                .await,
+            Err(Error::Timeout)
+        ));
+        tokio::select! {
+            _ = client.set_activity("Cancelled") => panic!("Discord doesn't respond yet"),
+            _ = tokio::time::sleep(Duration::from_millis(20)) => (),
+        }
+        assert!(matches!(
+            client.event().await,
+            Ok(crate::EventResponse::VoiceChannelSelect(_))
+        ));
+        client.set_activity("Testing").await.unwrap();
+        discord.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn clear_activity_and_guilds() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            let command = discord.expect_command("SET_ACTIVITY");
+            assert!(command["args"].get("activity").is_none());
+            discord.respond("SET_ACTIVITY", json!(null));
+            discord.expect_command("GET_GUILDS");
+            discord.respond(
+                "GET_GUILDS",
+                json!({ "guilds": [{ "id": "1", "name": "guild", "icon_url": null }] }),
+            );
+        });
+        let mut client = client(socket).await;
+        client.clear_activity().await.unwrap();
+        let guilds = client.get_guilds().await.unwrap().guilds;
+        assert_eq!(guilds.len(), 1);
+        // The CDN host sent in READY is used
+        assert_eq!(
+            client.cdn().avatar(Snowflake(7), "abc"),
+            "https://cdn.discordapp.com/avatars/7/abc.png"
+        );
+        discord.join().unwrap();
+    }
 }
@@ -2,32 +2,61 @@
 //! # Discord IPC Client
 
 pub mod activity;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cdn;
 pub mod channel;
+pub mod codec;
 pub mod command;
 pub mod discord;
 mod ipc;
+pub mod metrics;
+#[cfg(all(
+    test,
+    unix,
+    any(feature = "tokio", feature = "futures-io", feature = "blocking")
+))]
+mod mock;
+// `mock` names the crate, as it's shared with the binaries' and integration tests
+#[cfg(test)]
+extern crate self as discord_ipc;
+#[cfg(feature = "mpris")]
+pub mod mpris;
 pub mod oauth;
 mod payload;
+#[cfg(any(feature = "tokio", feature = "futures-io", feature = "blocking"))]
 mod platform;
+pub mod presence;
+pub mod record;
+pub mod transport;
 pub mod voice;
 
 pub use command::{EventResponse as Event, EventSubscribe};
 use ipc::Framed;
+pub use oauth::{FileSaver, OauthScope, TokenSaver};
 use oauth::{NoneSaver, SecretType};
-pub use oauth::{OauthScope, TokenSaver};
 
-use std::io;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use activity::Activity;
+use cdn::Cdn;
 use channel::PartialUser;
-use command::{Command, EventResponse, GetChannel, Subscribe};
+use command::{
+    CaptureShortcutAction, Command, EventResponse, GetChannel, GetChannels, GetGuilds,
+    GetVoiceSettings, SetUserVoiceSettings, Subscribe,
+};
 use discord::Snowflake;
 use log::*;
-use platform::PlatformSocket;
-use serde::{Deserialize, Serialize};
+use serde::{de::IgnoredAny, Deserialize};
 use thiserror::Error;
-
-use crate::ipc::OpCode;
+use voice::{
+    DeviceMatcher, JoinOptions, Pan, ShortcutCapture, UserVoicePatch, VoiceSettingsPatch, Volume,
+};
 
 /// An Error returned by the library
 #[derive(Debug, Error)]
@@ -44,18 +73,104 @@ pub enum Error {
     /// An Invalid event was sent by Discord
     #[error("Invalid Event: {0}")]
     InvalidEvent(String),
-    /// The connection has been closed by Discord
+    /// Discord sent an event that couldn't be parsed. Other events can still be received
+    #[error("Malformed {evt} event: {source}")]
+    MalformedEvent {
+        /// Event name
+        evt: String,
+        /// Parsing error
+        source: serde_json::Error,
+    },
+    /// The connection has been closed, e.g. because Discord exited
     #[error("Pipe has been closed")]
     PipeClosed,
+    /// Discord closed the connection, giving a reason
+    #[error("Connection closed by Discord ({code:?}): {message}")]
+    Closed {
+        /// Close code
+        code: command::CloseCode,
+        /// Human readable reason
+        message: String,
+    },
+    /// A frame longer than the configured maximum was received. The connection can't be used
+    /// after this error
+    #[error("Frame length {len} exceeds the maximum of {max}")]
+    FrameTooLarge {
+        /// Length of the frame's payload
+        len: usize,
+        /// Maximum payload length
+        max: usize,
+    },
+    /// A frame with an unknown opcode was received. The frame has been skipped
+    #[error("Unknown opcode: {0}")]
+    UnknownOpcode(u32),
     /// Discord sent an unexpected response
     #[error("Response Not Expected")]
     UnexpectedResponse,
+    /// Discord responded to a different command than the one that was sent
+    #[error("Expected a response to {expected}, got {got}")]
+    ResponseMismatch {
+        /// Command that was sent
+        expected: command::ResponseId,
+        /// Command the response was for
+        got: command::ResponseId,
+    },
+    /// Discord didn't respond in time, see [`ClientBuilder::timeout`]. The connection can still
+    /// be used
+    #[error("Timed out waiting for a response")]
+    Timeout,
     /// Discord sent an unexpected event
     #[error("Event Not Expected")]
     UnexpectedEvent,
     /// Discord sent an error in response to a command or subscribe request
     #[error("Discord Error: {0:?}")]
     Discord(command::Error),
+    /// D-Bus error while reading MPRIS players
+    #[cfg(feature = "mpris")]
+    #[error("D-Bus error: {0}")]
+    DBus(#[from] zbus::Error),
+    /// No audio device matched, see [`voice::DeviceMatcher`]
+    #[error("No audio device matches {0}")]
+    DeviceNotFound(String),
+    /// Several audio devices matched a substring, see [`voice::DeviceMatcher`]
+    #[error("Several audio devices match {matcher}: {candidates:?}")]
+    AmbiguousDevice {
+        /// The matcher
+        matcher: String,
+        /// Names of the matching devices
+        candidates: Vec<String>,
+    },
+    /// A presence template couldn't be loaded or rendered, see [`presence::template`]
+    #[error("Invalid presence template: {0}")]
+    InvalidTemplate(String),
+    /// Discord didn't finish selecting a channel within [`voice::JoinOptions::timeout`]
+    #[error("Timed out selecting the channel")]
+    SelectChannelTimedOut,
+    /// The user is already in a voice channel, and moving them requires
+    /// [`voice::JoinOptions::force`]. Ask the user to confirm before forcing
+    #[error("Already in a voice channel, joining another must be forced")]
+    ForceRequired,
+}
+
+impl Error {
+    /// RPC error code, if Discord responded to a command with an error
+    pub fn rpc_code(&self) -> Option<command::RpcErrorCode> {
+        match self {
+            Self::Discord(e) => Some(e.code),
+            Self::SelectChannelTimedOut => Some(command::RpcErrorCode::SelectChannelTimedOut),
+            Self::ForceRequired => Some(command::RpcErrorCode::SelectVoiceForceRequired),
+            _ => None,
+        }
+    }
+
+    /// Give errors from selecting a channel their own variants
+    pub(crate) fn select_channel(self) -> Self {
+        match self.rpc_code() {
+            Some(command::RpcErrorCode::SelectChannelTimedOut) => Self::SelectChannelTimedOut,
+            Some(command::RpcErrorCode::SelectVoiceForceRequired) => Self::ForceRequired,
+            _ => self,
+        }
+    }
 }
 
 /// Result alias for `Result<T, Error>`
@@ -72,10 +187,26 @@ pub trait ConnectionBuilder {
     async fn connect() -> io::Result<Self::Socket>;
 }
 
-/// Convience trait for Socket types
-pub trait Connection: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin {}
-
-impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> Connection for T {}
+/// Runtime independent byte stream to a Discord client
+///
+/// Streams from tokio or `futures-io` based runtimes (async-std, smol) can be used through the
+/// adapters in [`transport`].
+pub trait Connection: Unpin {
+    /// Attempt to read bytes into `buf`, returning the number of bytes read
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>;
+    /// Attempt to write bytes from `buf`, returning the number of bytes written
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+    /// Attempt to flush buffered data
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
 
 /// Client construct
 pub struct Client<C> {
@@ -85,6 +216,7 @@ pub struct Client<C> {
 
 impl Client<()> {
     /// Create a new ClientBuilder with the specified client_id
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(client_id: u64) -> ClientBuilder {
         ClientBuilder::new(client_id)
     }
@@ -94,49 +226,250 @@ impl<C: Connection> Client<C> {
     /// Update the User's current activity
     pub async fn set_activity(&mut self, activity: impl Into<Activity>) -> Result<Activity> {
         self.framed
-            .send_command(Command::SetActivity {
+            .command(Command::SetActivity {
                 pid: std::process::id(),
-                activity: activity.into(),
+                activity: Some(activity.into()),
+            })
+            .await
+    }
+
+    /// Clear the User's current activity
+    pub async fn clear_activity(&mut self) -> Result<()> {
+        self.framed
+            .command::<IgnoredAny>(Command::SetActivity {
+                pid: std::process::id(),
+                activity: None,
+            })
+            .await
+            .map(|_| ())
+    }
+
+    /// List the guilds the user is in. Requires the `rpc` scope
+    pub async fn get_guilds(&mut self) -> Result<GetGuilds> {
+        self.framed.command(Command::GetGuilds {}).await
+    }
+
+    /// List the channels of a guild. Requires the `rpc` scope
+    pub async fn get_channels(&mut self, guild_id: Snowflake) -> Result<GetChannels> {
+        self.framed.command(Command::GetChannels { guild_id }).await
+    }
+
+    /// Get a channel, including the voice states of its members. Requires the `rpc` scope
+    pub async fn get_channel(&mut self, channel_id: Snowflake) -> Result<GetChannel> {
+        self.framed
+            .command(Command::GetChannel { channel_id })
+            .await
+    }
+
+    /// Get the user's voice settings. Requires the `rpc` scope
+    pub async fn get_voice_settings(&mut self) -> Result<GetVoiceSettings> {
+        self.framed.command(Command::GetVoiceSettings {}).await
+    }
+
+    /// Change the user's voice settings, returning the new settings. Takes a
+    /// [`VoiceSettingsPatch`] or a [`SetVoiceSettings`](command::SetVoiceSettings). Requires the
+    /// `rpc` scope
+    pub async fn set_voice_settings(
+        &mut self,
+        settings: impl Into<VoiceSettingsPatch>,
+    ) -> Result<GetVoiceSettings> {
+        self.framed
+            .command(Command::SetVoiceSettings(settings.into()))
+            .await
+    }
+
+    /// Select the input device matching `matcher`, returning the new settings. Requires the `rpc`
+    /// scope
+    pub async fn select_input_device(
+        &mut self,
+        matcher: impl Into<DeviceMatcher>,
+    ) -> Result<GetVoiceSettings> {
+        let settings = self.get_voice_settings().await?;
+        let devices = settings
+            .input
+            .map(|i| i.available_devices)
+            .unwrap_or_default();
+        let device = matcher.into().find(&devices)?;
+        self.set_voice_settings(VoiceSettingsPatch::new().input_device(&device.id))
+            .await
+    }
+
+    /// Select the output device matching `matcher`, returning the new settings. Requires the
+    /// `rpc` scope
+    pub async fn select_output_device(
+        &mut self,
+        matcher: impl Into<DeviceMatcher>,
+    ) -> Result<GetVoiceSettings> {
+        let settings = self.get_voice_settings().await?;
+        let devices = settings
+            .output
+            .map(|o| o.available_devices)
+            .unwrap_or_default();
+        let device = matcher.into().find(&devices)?;
+        self.set_voice_settings(VoiceSettingsPatch::new().output_device(&device.id))
+            .await
+    }
+
+    /// Change the voice settings of another user, returning their new settings. Requires the
+    /// `rpc` scope
+    pub async fn set_user_voice_settings(
+        &mut self,
+        user_id: Snowflake,
+        patch: UserVoicePatch,
+    ) -> Result<SetUserVoiceSettings> {
+        self.framed
+            .command(Command::SetUserVoiceSettings { user_id, patch })
+            .await
+    }
+
+    /// Change the volume of another user, returning their new settings. Requires the `rpc` scope
+    pub async fn set_user_volume(
+        &mut self,
+        user_id: Snowflake,
+        volume: Volume,
+    ) -> Result<SetUserVoiceSettings> {
+        self.set_user_voice_settings(user_id, UserVoicePatch::new().volume(volume))
+            .await
+    }
+
+    /// Change the left/right balance of another user, returning their new settings. Requires the
+    /// `rpc` scope
+    pub async fn set_user_pan(
+        &mut self,
+        user_id: Snowflake,
+        pan: Pan,
+    ) -> Result<SetUserVoiceSettings> {
+        self.set_user_voice_settings(user_id, UserVoicePatch::new().pan(pan))
+            .await
+    }
+
+    /// Mute or unmute another user for the current user only, returning their new settings.
+    /// Requires the `rpc` scope
+    pub async fn set_user_mute(
+        &mut self,
+        user_id: Snowflake,
+        mute: bool,
+    ) -> Result<SetUserVoiceSettings> {
+        self.set_user_voice_settings(user_id, UserVoicePatch::new().mute(mute))
+            .await
+    }
+
+    /// Set the volume of everyone else in the selected voice channel, returning their new
+    /// settings. Returns nothing if the user isn't in a voice channel. Requires the `rpc` scope
+    pub async fn set_channel_volume(
+        &mut self,
+        volume: Volume,
+    ) -> Result<Vec<SetUserVoiceSettings>> {
+        let Some(channel) = self.get_selected_channel().await? else {
+            return Ok(Vec::new());
+        };
+        let others: Vec<_> = channel
+            .user_ids()
+            .filter(|&id| id != self.user.id)
+            .collect();
+        let mut updated = Vec::new();
+        for user_id in others {
+            updated.push(self.set_user_volume(user_id, volume).await?);
+        }
+        Ok(updated)
+    }
+
+    /// Start recording a shortcut, e.g. for push to talk. The capture returns each key
+    /// combination the user presses until it's stopped. Requires the `rpc` scope
+    ///
+    /// ```no_run
+    /// # async fn f(client: &mut discord_ipc::Client<impl discord_ipc::Connection>) {
+    /// let mut capture = client.capture_shortcut().await.unwrap();
+    /// let keys = capture.next().await.unwrap();
+    /// capture.stop().await.unwrap();
+    /// # }
+    /// ```
+    pub async fn capture_shortcut(&mut self) -> Result<ShortcutCapture<'_, C>> {
+        self.framed
+            .command::<IgnoredAny>(Command::CaptureShortcut {
+                action: CaptureShortcutAction::Start,
             })
             .await?;
-        self.framed.response().await
+        Ok(ShortcutCapture::new(self))
     }
 
     /// Get the user's selected voice channel
     pub async fn get_selected_channel(&mut self) -> Result<Option<GetChannel>> {
         self.framed
-            .send_command(Command::GetSelectedVoiceChannel {})
-            .await?
-            .response()
+            .command(Command::GetSelectedVoiceChannel {})
+            .await
+    }
+
+    /// Join a voice channel, returning it. Fails with [`Error::ForceRequired`] if the user is
+    /// already in a voice channel, unless [`JoinOptions::force`] is set. Requires the `rpc` scope
+    pub async fn join_voice(
+        &mut self,
+        channel_id: Snowflake,
+        options: JoinOptions,
+    ) -> Result<GetChannel> {
+        if let Some(timeout) = options.timeout_secs() {
+            // Leave Discord time to report its own timeout
+            self.framed
+                .extend_next_timeout(Duration::from_secs(timeout + 1));
+        }
+        self.framed
+            .command(options.command(channel_id))
+            .await
+            .map_err(Error::select_channel)
+    }
+
+    /// Leave the current voice channel. Requires the `rpc` scope
+    pub async fn leave_voice(&mut self) -> Result<()> {
+        self.framed
+            .command::<IgnoredAny>(Command::SelectVoiceChannel {
+                channel_id: None,
+                timeout: None,
+                force: None,
+            })
             .await
+            .map(|_| ())
+            .map_err(Error::select_channel)
+    }
+
+    /// Open a text channel in Discord, returning it. Requires the `rpc` scope
+    pub async fn focus_text_channel(&mut self, channel_id: Snowflake) -> Result<GetChannel> {
+        self.framed
+            .command(Command::SelectTextChannel {
+                channel_id,
+                timeout: None,
+            })
+            .await
+            .map_err(Error::select_channel)
     }
 
     /// Subscribe to an event. Use `.event().await` to wait for events
     pub async fn subscribe(&mut self, event: EventSubscribe) -> Result<()> {
-        self.framed.refresh_auth().await?;
         #[derive(Deserialize)]
         struct SubRes {}
         self.framed
-            .send_message(OpCode::FRAME, Subscribe::sub(event))
-            .await?
-            .response::<SubRes>()
+            .request::<_, SubRes>(Subscribe::sub(event))
             .await
             .map(|_| ())
     }
 
     /// Unsubscribe from events previously subscribed to
     pub async fn unsubscribe(&mut self, event: EventSubscribe) -> Result<()> {
-        self.framed.refresh_auth().await?;
         #[derive(Deserialize)]
         struct SubRes {}
         self.framed
-            .send_message(OpCode::FRAME, Subscribe::unsub(event))
-            .await?;
-        self.framed.response::<SubRes>().await.map(|_| ())
+            .request::<_, SubRes>(Subscribe::unsub(event))
+            .await
+            .map(|_| ())
     }
 
     /// Wait for a discord event to be sent
     pub async fn event(&mut self) -> Result<EventResponse> {
+        self.framed.event().await.map(|(e, _)| e)
+    }
+
+    /// Wait for a discord event to be sent, along with its raw `data`. The data is only kept if
+    /// enabled with [`ClientBuilder::keep_raw_json`]
+    pub async fn event_with_raw(&mut self) -> Result<(EventResponse, Option<serde_json::Value>)> {
         self.framed.event().await
     }
 
@@ -144,6 +477,25 @@ impl<C: Connection> Client<C> {
     pub fn user(&self) -> &PartialUser {
         &self.user
     }
+
+    /// Build image URLs on the CDN host Discord sent during connection
+    pub fn cdn(&self) -> Cdn {
+        Cdn::new(&self.framed.config.cdn_host)
+    }
+
+    /// Set the timeout for the response to the next command, overriding the default set with
+    /// [`ClientBuilder::timeout`]
+    ///
+    /// ```no_run
+    /// # async fn f(client: &mut discord_ipc::Client<impl discord_ipc::Connection>) {
+    /// # use std::time::Duration;
+    /// client.timeout(Duration::from_secs(1)).set_activity("Testing").await;
+    /// # }
+    /// ```
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.framed.set_next_timeout(timeout);
+        self
+    }
 }
 
 /// Builder for the `Client` struct
@@ -152,6 +504,11 @@ pub struct ClientBuilder {
     scopes: Vec<OauthScope>,
     secret: Option<SecretType>,
     save_refresh: Box<dyn TokenSaver>,
+    max_frame_len: usize,
+    keep_raw: bool,
+    lenient: bool,
+    timeout: Option<Duration>,
+    recorder: Option<record::Recorder>,
 }
 
 impl ClientBuilder {
@@ -161,6 +518,11 @@ impl ClientBuilder {
             secret: None,
             scopes: vec![OauthScope::Rpc],
             save_refresh: Box::new(NoneSaver),
+            max_frame_len: codec::DEFAULT_MAX_FRAME_LEN,
+            keep_raw: false,
+            lenient: false,
+            timeout: None,
+            recorder: None,
         }
     }
 
@@ -195,18 +557,70 @@ impl ClientBuilder {
         self.save_refresh = Box::new(token);
         self
     }
-}
 
-#[derive(Debug, Serialize)]
-struct HandshakeRequest {
-    v: usize,
-    client_id: Snowflake,
+    /// Set the maximum payload length accepted from Discord. Longer frames fail with
+    /// [`Error::FrameTooLarge`] before anything is allocated for them. Defaults to
+    /// [`codec::DEFAULT_MAX_FRAME_LEN`]
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Keep the raw `data` of events, including fields this library doesn't know about. Use
+    /// [`Client::event_with_raw`] to receive it
+    pub fn keep_raw_json(mut self, keep: bool) -> Self {
+        self.keep_raw = keep;
+        self
+    }
+
+    /// Return events that fail to parse as [`Event::Malformed`] instead of
+    /// [`Error::MalformedEvent`]
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Fail with [`Error::Timeout`] if Discord doesn't respond to a command within `timeout`.
+    /// Defaults to waiting forever. Use [`Client::timeout`] to override it for a single command
+    ///
+    /// Timers run on tokio when used within a tokio runtime, otherwise on `async-io` if the
+    /// `futures-io` feature is enabled, falling back to a timer thread. The blocking client
+    /// doesn't support timeouts
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Record every frame sent & received, see [`record`]. Tokens are redacted, but
+    /// recordings can still contain private information, such as messages & user names
+    pub fn record(mut self, recorder: record::Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    fn parse_options(&self) -> payload::ParseOptions {
+        payload::ParseOptions {
+            keep_raw: self.keep_raw,
+            lenient: self.lenient,
+        }
+    }
 }
 
 impl ClientBuilder {
     /// Connect to a running Discord client and authenticate
-    pub async fn connect(self) -> Result<Client<<PlatformSocket as ConnectionBuilder>::Socket>> {
-        let connection = PlatformSocket::connect().await?;
+    ///
+    /// The socket is opened with tokio if the `tokio` feature is enabled, otherwise with
+    /// `async-io` (unix only) if the `futures-io` feature is enabled.
+    #[cfg(any(feature = "tokio", all(unix, feature = "futures-io")))]
+    pub async fn connect(
+        self,
+    ) -> Result<Client<<platform::PlatformSocket as ConnectionBuilder>::Socket>> {
+        let connection = platform::PlatformSocket::connect().await?;
+        self.connect_with(connection).await
+    }
+
+    /// Authenticate over an already opened connection to a Discord client
+    pub async fn connect_with<C: Connection>(self, connection: C) -> Result<Client<C>> {
         let (framed, user) = Framed::connect(self, connection).await?;
         Ok(Client { framed, user })
     }
@@ -0,0 +1,75 @@
+//! Process wide client counters
+//!
+//! Counters are shared by every client in the process, async & blocking, and are always
+//! collected. With the `tracing` feature, the client also emits spans for connecting,
+//! authenticating, commands & events.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static CONNECTS: AtomicU64 = AtomicU64::new(0);
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+static COMMANDS: AtomicU64 = AtomicU64::new(0);
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Snapshot of the client counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Successful connections
+    pub connects: u64,
+    /// Connections made after losing a previous one, see [`reconnected`]
+    pub reconnects: u64,
+    /// Commands sent, including subscriptions & authentication
+    pub commands: u64,
+    /// Events received
+    pub events: u64,
+    /// Errors returned from connecting, commands & waiting for events, counted once per call
+    pub errors: u64,
+}
+
+/// Current value of the counters
+pub fn snapshot() -> Metrics {
+    Metrics {
+        connects: CONNECTS.load(Ordering::Relaxed),
+        reconnects: RECONNECTS.load(Ordering::Relaxed),
+        commands: COMMANDS.load(Ordering::Relaxed),
+        events: EVENTS.load(Ordering::Relaxed),
+        errors: ERRORS.load(Ordering::Relaxed),
+    }
+}
+
+/// Count a reconnection. Clients don't reconnect by themselves, so programs that connect again
+/// after losing a connection call this once the new connection succeeds
+pub fn reconnected() {
+    RECONNECTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn connected() {
+    CONNECTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn command() {
+    COMMANDS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn event() {
+    EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count `res` if it's an error, passing it through
+pub(crate) fn count<T>(res: crate::Result<T>) -> crate::Result<T> {
+    if res.is_err() {
+        ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+    res
+}
+
+/// Record a field on the current span, if the `tracing` feature is enabled. Fields the span
+/// doesn't declare are ignored
+macro_rules! span_record {
+    ($field:literal, $value:expr) => {
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record($field, $value);
+    };
+}
+pub(crate) use span_record;
@@ -0,0 +1,153 @@
+//! Scripted Discord client used by tests
+//!
+//! Only uses the public API, so that the binaries' and integration tests can include it too
+
+// Not every feature combination runs the tests using every helper
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    thread::{self, JoinHandle},
+};
+
+use serde_json::{json, Value};
+
+use discord_ipc::codec::{self, OpCode};
+#[cfg(feature = "tokio")]
+use discord_ipc::{transport::TokioIo, Client};
+
+/// Server end of a socket pair, playing the part of the Discord client
+pub(crate) struct MockDiscord {
+    socket: UnixStream,
+    /// Nonce of the last command read, echoed by `respond`
+    nonce: Value,
+}
+
+impl MockDiscord {
+    /// Run `script` against the server end of a new socket pair, returning the client end
+    pub fn spawn(
+        script: impl FnOnce(MockDiscord) + Send + 'static,
+    ) -> (UnixStream, JoinHandle<()>) {
+        let (client, socket) = UnixStream::pair().unwrap();
+        (
+            client,
+            thread::spawn(move || {
+                script(MockDiscord {
+                    socket,
+                    nonce: Value::Null,
+                })
+            }),
+        )
+    }
+
+    pub fn read_frame(&mut self) -> (u32, Value) {
+        let mut header = [0u8; codec::HEADER_LEN];
+        self.socket.read_exact(&mut header).unwrap();
+        let opcode = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let mut body = vec![0u8; u32::from_le_bytes(header[4..].try_into().unwrap()) as usize];
+        self.socket.read_exact(&mut body).unwrap();
+        (opcode, serde_json::from_slice(&body).unwrap())
+    }
+
+    pub fn write_raw(&mut self, bytes: &[u8]) {
+        self.socket.write_all(bytes).unwrap();
+    }
+
+    pub fn write_frame(&mut self, opcode: OpCode, message: Value) {
+        let mut buffer = vec![];
+        codec::encode_frame(&mut buffer, opcode, &message).unwrap();
+        self.socket.write_all(&buffer).unwrap();
+    }
+
+    /// Read the next command, checking its name. Returns the full payload
+    pub fn expect_command(&mut self, cmd: &str) -> Value {
+        let (opcode, payload) = self.read_frame();
+        assert_eq!(opcode, OpCode::FRAME as u32);
+        assert_eq!(payload["cmd"], cmd);
+        self.nonce = payload["nonce"].clone();
+        payload
+    }
+
+    /// Reply to a command with `data`
+    pub fn respond(&mut self, cmd: &str, data: Value) {
+        self.write_frame(
+            OpCode::FRAME,
+            json!({ "cmd": cmd, "evt": null, "data": data, "nonce": self.nonce }),
+        );
+    }
+
+    /// Dispatch an event
+    pub fn dispatch(&mut self, evt: &str, data: Value) {
+        self.write_frame(
+            OpCode::FRAME,
+            json!({ "cmd": "DISPATCH", "evt": evt, "data": data }),
+        );
+    }
+
+    /// Accept the handshake from client 42 and send the READY event
+    pub fn handshake(&mut self) {
+        let (opcode, handshake) = self.read_frame();
+        assert_eq!(opcode, OpCode::HANDSHAKE as u32);
+        assert_eq!(handshake, json!({ "v": 1, "client_id": "42" }));
+        self.dispatch(
+            "READY",
+            json!({
+                "v": 1,
+                "config": {
+                    "cdn_host": "cdn.discordapp.com",
+                    "api_endpoint": "//discord.com/api",
+                    "environment": "production"
+                },
+                "user": { "id": "7", "username": "user", "discriminator": "0001", "avatar": null }
+            }),
+        );
+    }
+}
+
+/// Make the client end of a socket pair usable by tokio
+#[cfg(feature = "tokio")]
+pub(crate) fn tokio_socket(socket: UnixStream) -> TokioIo<tokio::net::UnixStream> {
+    socket.set_nonblocking(true).unwrap();
+    TokioIo(tokio::net::UnixStream::from_std(socket).unwrap())
+}
+
+/// Connect as client 42 over the client end of a socket pair
+#[cfg(feature = "tokio")]
+pub(crate) async fn client(socket: UnixStream) -> Client<TokioIo<tokio::net::UnixStream>> {
+    Client::new(42)
+        .connect_with(tokio_socket(socket))
+        .await
+        .unwrap()
+}
+
+/// Voice state of a channel member, as sent by Discord
+pub(crate) fn voice_state(user_id: &str, self_mute: bool) -> Value {
+    json!({
+        "nick": null,
+        "mute": false,
+        "volume": 100,
+        "pan": { "left": 1.0, "right": 1.0 },
+        "voice_state": {
+            "mute": false,
+            "deaf": false,
+            "self_mute": self_mute,
+            "self_deaf": false,
+            "suppress": false
+        },
+        "user": { "id": user_id, "username": "user", "discriminator": "0", "avatar": null }
+    })
+}
+
+/// Voice channel with `voice_states`, as sent by Discord
+pub(crate) fn channel(id: &str, voice_states: Value) -> Value {
+    json!({
+        "id": id,
+        "guild_id": "1",
+        "name": "Voice",
+        "type": 2,
+        "user_limit": 0,
+        "position": 0,
+        "voice_states": voice_states
+    })
+}
@@ -0,0 +1,491 @@
+//! Current song from MPRIS media players
+//!
+//! Media players on Linux publish what they're playing over D-Bus with
+//! [MPRIS](https://specifications.freedesktop.org/mpris-spec/latest/). [`Mpris`] reads the
+//! players on a session bus, and [`Mpris::run`] shows the current song as an
+//! [`ActivityType::Listening`] activity, cleared when playback pauses or stops.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use log::*;
+use zbus::{proxy::CacheProperties, zvariant::Value};
+
+use crate::{
+    activity::{Activity, ActivityType},
+    Client, Connection, Error, Result,
+};
+
+/// Prefix of the bus names of MPRIS players
+const BUS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// How often [`Mpris::run`] polls the players
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far the start of a track can move before the activity is updated. Players report their
+/// position a little late, so small differences are ignored rather than updating every poll
+const SEEK_TOLERANCE: Duration = Duration::from_secs(2);
+
+#[zbus::proxy(
+    interface = "org.mpris.MediaPlayer2.Player",
+    default_path = "/org/mpris/MediaPlayer2",
+    gen_blocking = false
+)]
+trait Player {
+    #[zbus(property)]
+    fn playback_status(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn metadata(&self) -> zbus::Result<HashMap<String, zbus::zvariant::OwnedValue>>;
+
+    #[zbus(property)]
+    fn position(&self) -> zbus::Result<i64>;
+}
+
+/// Playback status of a player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    /// A track is playing
+    Playing,
+    /// A track is paused
+    Paused,
+    /// Nothing is playing
+    Stopped,
+}
+
+/// Track metadata
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Track {
+    /// Track title
+    pub title: Option<String>,
+    /// Track artists
+    pub artists: Vec<String>,
+    /// Album name
+    pub album: Option<String>,
+    /// Track length
+    pub length: Option<Duration>,
+    /// Album art URL. Only `http(s)` URLs are shown, Discord can't load local files
+    pub art_url: Option<String>,
+}
+
+impl Track {
+    fn from_metadata(metadata: &HashMap<String, zbus::zvariant::OwnedValue>) -> Self {
+        let get = |key: &str| metadata.get(key).map(|value| &**value);
+        Self {
+            title: get("xesam:title").and_then(string),
+            artists: get("xesam:artist").map(strings).unwrap_or_default(),
+            album: get("xesam:album").and_then(string),
+            length: get("mpris:length")
+                .and_then(micros)
+                .filter(|length| !length.is_zero()),
+            art_url: get("mpris:artUrl").and_then(string),
+        }
+    }
+}
+
+fn string(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(s) if !s.is_empty() => Some(s.to_string()),
+        Value::Value(value) => string(value),
+        _ => None,
+    }
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().filter_map(string).collect(),
+        Value::Value(value) => strings(value),
+        value => string(value).into_iter().collect(),
+    }
+}
+
+/// Duration from microseconds. Some players send lengths unsigned, against the spec
+fn micros(value: &Value) -> Option<Duration> {
+    let micros = match value {
+        Value::I64(n) => u64::try_from(*n).ok()?,
+        Value::U64(n) => *n,
+        Value::I32(n) => u64::try_from(*n).ok()?,
+        Value::U32(n) => u64::from(*n),
+        Value::Value(value) => return micros(value),
+        _ => return None,
+    };
+    Some(Duration::from_micros(micros))
+}
+
+/// State of a player
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerState {
+    /// Bus name of the player, e.g. `org.mpris.MediaPlayer2.spotify`
+    pub player: String,
+    /// Playback status
+    pub status: PlaybackStatus,
+    /// Current track
+    pub track: Track,
+    /// Position in the track
+    pub position: Duration,
+}
+
+impl PlayerState {
+    /// When the track would have started if played without pauses, given that the state was read
+    /// at `now`
+    pub fn started(&self, now: SystemTime) -> SystemTime {
+        now.checked_sub(self.position).unwrap_or(now)
+    }
+
+    /// Activity showing the track, given that the state was read at `now`. `None` unless playing
+    ///
+    /// The title is the first line and the artists the second, with the remaining time if the
+    /// track length is known.
+    pub fn activity(&self, now: SystemTime) -> Option<Activity> {
+        if self.status != PlaybackStatus::Playing {
+            return None;
+        }
+        let track = &self.track;
+        let mut activity = Activity::new().activity_type(ActivityType::Listening);
+        if let Some(title) = &track.title {
+            activity = activity.details(title);
+        }
+        if !track.artists.is_empty() {
+            activity = activity.state(track.artists.join(", "));
+        }
+        if let Some(url) = &track.art_url {
+            if url.starts_with("https://") || url.starts_with("http://") {
+                activity = activity.large_image(url, track.album.clone());
+            }
+        }
+        let started = self.started(now);
+        let end = track.length.map(|length| (started + length).into());
+        Some(activity.timestamps(Some(started.into()), end))
+    }
+}
+
+/// MPRIS players on a D-Bus connection
+#[derive(Debug, Clone)]
+pub struct Mpris {
+    connection: zbus::Connection,
+    player: Option<String>,
+}
+
+impl Mpris {
+    /// Connect to the session bus
+    pub async fn session() -> Result<Self> {
+        Ok(Self::new(zbus::Connection::session().await?))
+    }
+
+    /// Use an existing D-Bus connection
+    pub fn new(connection: zbus::Connection) -> Self {
+        Self {
+            connection,
+            player: None,
+        }
+    }
+
+    /// Only use players whose bus name contains `name`, e.g. `spotify`
+    pub fn player(mut self, name: impl Into<String>) -> Self {
+        self.player = Some(name.into());
+        self
+    }
+
+    /// Bus names of the players
+    pub async fn players(&self) -> Result<Vec<String>> {
+        let dbus = zbus::fdo::DBusProxy::new(&self.connection).await?;
+        let mut players: Vec<_> = dbus
+            .list_names()
+            .await
+            .map_err(zbus::Error::from)?
+            .into_iter()
+            .map(|name| name.to_string())
+            .filter(|name| name.starts_with(BUS_PREFIX))
+            .filter(|name| {
+                self.player
+                    .as_ref()
+                    .is_none_or(|player| name[BUS_PREFIX.len()..].contains(player.as_str()))
+            })
+            .collect();
+        players.sort();
+        Ok(players)
+    }
+
+    /// State of a player
+    pub async fn player_state(&self, player: &str) -> Result<PlayerState> {
+        let proxy = PlayerProxy::builder(&self.connection)
+            .destination(player.to_string())?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        let status = match proxy.playback_status().await?.as_str() {
+            "Playing" => PlaybackStatus::Playing,
+            "Paused" => PlaybackStatus::Paused,
+            _ => PlaybackStatus::Stopped,
+        };
+        let track = Track::from_metadata(&proxy.metadata().await?);
+        // Position is optional for players that can't seek
+        let position = proxy.position().await.unwrap_or_default();
+        Ok(PlayerState {
+            player: player.to_string(),
+            status,
+            track,
+            position: Duration::from_micros(position.max(0) as u64),
+        })
+    }
+
+    /// State of the first playing player, or of the first player if none are playing. `None`
+    /// if there are no players
+    pub async fn state(&self) -> Result<Option<PlayerState>> {
+        let mut first = None;
+        for player in self.players().await? {
+            match self.player_state(&player).await {
+                Ok(state) if state.status == PlaybackStatus::Playing => return Ok(Some(state)),
+                Ok(state) => {
+                    first.get_or_insert(state);
+                }
+                // Players can exit between listing & querying them
+                Err(e) => debug!("Failed to read {player}: {e}"),
+            }
+        }
+        Ok(first)
+    }
+
+    /// Keep the activity of `client` showing the current song until the connection fails
+    ///
+    /// The activity is updated when the track changes or is seeked, and cleared when playback
+    /// pauses or stops. Activities Discord rejects are logged.
+    pub async fn run<C: Connection>(&self, client: &mut Client<C>) -> Result<()> {
+        let mut shown = Shown::default();
+        loop {
+            let now = SystemTime::now();
+            let state = match self.state().await {
+                Ok(state) => state.filter(|s| s.status == PlaybackStatus::Playing),
+                Err(e) => {
+                    warn!("Failed to read MPRIS players: {e}");
+                    None
+                }
+            };
+            if shown.update(state.as_ref(), now) {
+                let res = match state.and_then(|state| state.activity(now)) {
+                    Some(activity) => client.set_activity(activity).await.map(|_| ()),
+                    None => client.clear_activity().await,
+                };
+                match res {
+                    Ok(()) => (),
+                    Err(Error::Discord(e)) => warn!("Discord rejected the activity: {e:?}"),
+                    Err(e) => return Err(e),
+                }
+            }
+            crate::transport::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// The track shown, to avoid sending the same activity every poll
+#[derive(Debug, Default)]
+struct Shown(Option<(Track, SystemTime)>);
+
+impl Shown {
+    /// Record the playing state, returning whether the activity needs updating
+    fn update(&mut self, playing: Option<&PlayerState>, now: SystemTime) -> bool {
+        let next = playing.map(|state| (state.track.clone(), state.started(now)));
+        let changed = match (&self.0, &next) {
+            (None, None) => false,
+            (Some((shown, shown_start)), Some((track, start))) => {
+                let drift = start
+                    .duration_since(*shown_start)
+                    .or_else(|_| shown_start.duration_since(*start))
+                    .unwrap_or_default();
+                shown != track || drift > SEEK_TOLERANCE
+            }
+            _ => true,
+        };
+        if changed {
+            self.0 = next;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+
+    use serde_json::json;
+    use zbus::zvariant::OwnedValue;
+
+    use super::*;
+
+    fn state(status: PlaybackStatus, position: u64) -> PlayerState {
+        PlayerState {
+            player: format!("{BUS_PREFIX}test"),
+            status,
+            track: Track {
+                title: Some("Song".into()),
+                artists: vec!["Artist".into(), "Other".into()],
+                album: Some("Album".into()),
+                length: Some(Duration::from_secs(200)),
+                art_url: Some("https://example.com/art.jpg".into()),
+            },
+            position: Duration::from_secs(position),
+        }
+    }
+
+    #[test]
+    fn maps_tracks_to_activities() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let activity = state(PlaybackStatus::Playing, 50).activity(now).unwrap();
+        assert_eq!(
+            serde_json::to_value(&activity).unwrap(),
+            json!({
+                "type": 2,
+                "details": "Song",
+                "state": "Artist, Other",
+                "timestamps": { "start": 950_000, "end": 1_150_000 },
+                "assets": {
+                    "large_image": "https://example.com/art.jpg",
+                    "large_text": "Album",
+                    "small_image": null,
+                    "small_text": null
+                }
+            })
+        );
+        assert_eq!(state(PlaybackStatus::Paused, 50).activity(now), None);
+    }
+
+    #[test]
+    fn updates_on_changes_and_seeks() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut shown = Shown::default();
+        assert!(!shown.update(None, now));
+        assert!(shown.update(Some(&state(PlaybackStatus::Playing, 50)), now));
+        let later = now + Duration::from_secs(10);
+        assert!(!shown.update(Some(&state(PlaybackStatus::Playing, 60)), later));
+        assert!(shown.update(Some(&state(PlaybackStatus::Playing, 120)), later));
+        let mut next = state(PlaybackStatus::Playing, 120);
+        next.track.title = Some("Next".into());
+        assert!(shown.update(Some(&next), later));
+        assert!(shown.update(None, later));
+    }
+
+    /// Private session bus, stopped on drop
+    struct Bus(Child, String);
+
+    impl Bus {
+        fn spawn() -> Self {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon runs");
+            let mut address = String::new();
+            BufReader::new(child.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self(child, address.trim().to_string())
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    struct MockPlayer {
+        status: String,
+        position: i64,
+    }
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl MockPlayer {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.status.clone()
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            let value = |v: Value| OwnedValue::try_from(v).unwrap();
+            HashMap::from([
+                ("xesam:title".into(), value("Song".into())),
+                ("xesam:artist".into(), value(vec!["Artist"].into())),
+                ("xesam:album".into(), value("Album".into())),
+                ("mpris:length".into(), value(200_000_000i64.into())),
+                ("mpris:artUrl".into(), value("file:///art.jpg".into())),
+            ])
+        }
+
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            self.position
+        }
+    }
+
+    async fn serve(address: &str, name: &str, status: &str) -> zbus::Connection {
+        zbus::connection::Builder::address(address)
+            .unwrap()
+            .name(format!("{BUS_PREFIX}{name}"))
+            .unwrap()
+            .serve_at(
+                "/org/mpris/MediaPlayer2",
+                MockPlayer {
+                    status: status.into(),
+                    position: 50_000_000,
+                },
+            )
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs dbus-daemon, run with `cargo test --features mpris -- --ignored`"]
+    async fn reads_players_from_the_bus() {
+        let bus = Bus::spawn();
+        let _paused = serve(&bus.1, "paused", "Paused").await;
+        let _playing = serve(&bus.1, "playing", "Playing").await;
+        let connection = zbus::connection::Builder::address(bus.1.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mpris = Mpris::new(connection);
+
+        assert_eq!(
+            mpris.players().await.unwrap(),
+            [
+                format!("{BUS_PREFIX}paused"),
+                format!("{BUS_PREFIX}playing")
+            ]
+        );
+        let state = mpris.state().await.unwrap().unwrap();
+        assert_eq!(state.player, format!("{BUS_PREFIX}playing"));
+        assert_eq!(state.status, PlaybackStatus::Playing);
+        assert_eq!(state.position, Duration::from_secs(50));
+        assert_eq!(
+            state.track,
+            Track {
+                title: Some("Song".into()),
+                artists: vec!["Artist".into()],
+                album: Some("Album".into()),
+                length: Some(Duration::from_secs(200)),
+                art_url: Some("file:///art.jpg".into()),
+            }
+        );
+
+        let paused = mpris
+            .clone()
+            .player("paused")
+            .state()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(paused.status, PlaybackStatus::Paused);
+        assert_eq!(paused.activity(SystemTime::now()), None);
+    }
+}
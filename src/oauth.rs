@@ -1,3 +1,5 @@
+//! OAuth2 scopes & token management
+
 use std::{
     io::{self, ErrorKind},
     path::PathBuf,
@@ -101,6 +103,7 @@ pub enum OauthScope {
     WebhookIncoming,
 }
 
+/// Application a user authenticated with, returned by `AUTHENTICATE`
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Application {
     description: String,
@@ -111,6 +114,7 @@ pub struct Application {
     name: String,
 }
 
+/// Request to refresh an access token with the application secret
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct TokenRefresh<'a> {
     pub(crate) grant_type: GrantType,
@@ -119,6 +123,7 @@ pub struct TokenRefresh<'a> {
     pub(crate) client_secret: &'a str,
 }
 
+/// Request to exchange an authorization code for a token with the application secret
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct TokenReq<'a> {
     pub(crate) grant_type: GrantType,
@@ -127,25 +132,31 @@ pub struct TokenReq<'a> {
     pub(crate) client_secret: &'a str,
 }
 
+/// OAuth2 grant type of a token request
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum GrantType {
+    /// Exchange an authorization code
     AuthorizationCode,
+    /// Refresh an access token
     RefreshToken,
 }
 
+/// Request to refresh an access token through a remote secret server
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct TokenRefreshServer<'a> {
     pub(crate) refresh_token: &'a str,
     pub(crate) client_id: Snowflake,
 }
 
+/// Request to exchange an authorization code for a token through a remote secret server
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct TokenReqServer<'a> {
     pub(crate) code: &'a str,
     pub(crate) client_id: Snowflake,
 }
 
+/// Token returned by Discord or a remote secret server
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TokenRes {
     pub(crate) access_token: String,
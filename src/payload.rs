@@ -1,21 +1,51 @@
+use log::*;
 use serde::Deserialize;
+use serde_json::Value;
 
 use crate::{
-    command::{self, EventResponse as Event},
+    command::{self, EventResponse as Event, ResponseId},
     Error, Result,
 };
 
 #[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum OutPayload<C> {
-    Event(Event),
+    /// Event, along with its raw `data` if requested
+    Event(Event, Option<Value>),
     Error(command::Error),
     Ready(command::Ready),
     CommandResponse(C),
 }
 
+/// How events are parsed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ParseOptions {
+    /// Keep the raw `data` of events
+    pub keep_raw: bool,
+    /// Return malformed events as `Event::Malformed` instead of failing
+    pub lenient: bool,
+}
+
 #[derive(Debug, Deserialize)]
 struct Params<'a> {
     evt: Option<&'a str>,
+    cmd: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Params<'_> {
+    /// The `cmd` & `nonce` echoed by a response or error, `None` for events
+    fn response_id(self) -> Option<ResponseId> {
+        (self.evt.is_none() || self.evt == Some("ERROR")).then(|| ResponseId {
+            cmd: self.cmd.unwrap_or_default(),
+            nonce: self.nonce,
+        })
+    }
+}
+
+/// Get the `cmd` & `nonce` of a response or error without parsing the rest of the payload
+pub(crate) fn response_id(s: &[u8]) -> Result<Option<ResponseId>> {
+    Ok(serde_json::from_slice::<Params>(s)?.response_id())
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,8 +53,23 @@ struct Data<D> {
     data: D,
 }
 
-pub(crate) fn parse_response<'a, C: Deserialize<'a>>(s: &'a [u8]) -> Result<OutPayload<C>> {
-    let Params { evt } = serde_json::from_slice(s)?;
+/// Parse a payload from Discord. If `expected` is set, responses & errors must echo its `cmd` &
+/// `nonce`, and are only deserialized if they do
+pub(crate) fn parse_response<'a, C: Deserialize<'a>>(
+    s: &'a [u8],
+    options: ParseOptions,
+    expected: Option<&ResponseId>,
+) -> Result<OutPayload<C>> {
+    let params: Params = serde_json::from_slice(s)?;
+    let evt = params.evt;
+    if let (Some(expected), Some(got)) = (expected, params.response_id()) {
+        if &got != expected {
+            return Err(Error::ResponseMismatch {
+                expected: expected.clone(),
+                got,
+            });
+        }
+    }
     if let Some(evt) = evt {
         if evt == "ERROR" {
             Ok(OutPayload::Error(
@@ -35,55 +80,29 @@ pub(crate) fn parse_response<'a, C: Deserialize<'a>>(s: &'a [u8]) -> Result<OutP
                 serde_json::from_slice::<Data<_>>(s)?.data,
             ))
         } else {
-            Ok(OutPayload::Event(match evt {
-                "GUILD_STATUS" => Event::GuildStatus(serde_json::from_slice::<Data<_>>(s)?.data),
-                "GUILD_CREATE" => Event::GuildCreate(serde_json::from_slice::<Data<_>>(s)?.data),
-                "CHANNEL_CREATE" => {
-                    Event::ChannelCreate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "VOICE_CHANNEL_SELECT" => {
-                    Event::VoiceChannelSelect(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "VOICE_STATE_CREATE" => {
-                    Event::VoiceStateCreate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "VOICE_STATE_UPDATE" => {
-                    Event::VoiceStateUpdate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "VOICE_STATE_DELETE" => {
-                    Event::VoiceStateDelete(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "VOICE_SETTINGS_UPDATE" => {
-                    Event::VoiceSettingsUpdate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "VOICE_CONNECTION_STATUS" => {
-                    Event::VoiceConnectionStatus(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "SPEAKING_START" => {
-                    Event::SpeakingStart(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "SPEAKING_STOP" => Event::SpeakingStop(serde_json::from_slice::<Data<_>>(s)?.data),
-                "MESSAGE_CREATE" => {
-                    Event::MessageCreate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "MESSAGE_UPDATE" => {
-                    Event::MessageUpdate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "MESSAGE_DELETE" => {
-                    Event::MessageDelete(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "NOTIFICATION_CREATE" => {
-                    Event::NotificationCreate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "ACTIVITY_JOIN" => Event::ActivityJoin(serde_json::from_slice::<Data<_>>(s)?.data),
-                "ACTIVITY_SPECTATE" => {
-                    Event::ActivitySpectate(serde_json::from_slice::<Data<_>>(s)?.data)
-                }
-                "ACTIVITY_JOIN_REQUEST" => {
-                    Event::ActivityJoinRequest(serde_json::from_slice::<Data<_>>(s)?.data)
+            let raw = if options.keep_raw || options.lenient {
+                Some(serde_json::from_slice::<Data<Value>>(s)?.data)
+            } else {
+                None
+            };
+            match parse_event(evt, s) {
+                Ok(event) => Ok(OutPayload::Event(event, raw.filter(|_| options.keep_raw))),
+                Err(e) if options.lenient => {
+                    warn!("Malformed {evt} event: {e}");
+                    Ok(OutPayload::Event(
+                        Event::Malformed {
+                            evt: evt.to_string(),
+                            data: raw.clone().unwrap_or_default(),
+                            error: e.to_string(),
+                        },
+                        raw.filter(|_| options.keep_raw),
+                    ))
                 }
-                _ => return Err(Error::InvalidEvent(evt.to_string())),
-            }))
+                Err(source) => Err(Error::MalformedEvent {
+                    evt: evt.to_string(),
+                    source,
+                }),
+            }
         }
     } else {
         Ok(OutPayload::CommandResponse(
@@ -92,17 +111,61 @@ pub(crate) fn parse_response<'a, C: Deserialize<'a>>(s: &'a [u8]) -> Result<OutP
     }
 }
 
+fn parse_event(evt: &str, s: &[u8]) -> serde_json::Result<Event> {
+    Ok(match evt {
+        "GUILD_STATUS" => Event::GuildStatus(serde_json::from_slice::<Data<_>>(s)?.data),
+        "GUILD_CREATE" => Event::GuildCreate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "CHANNEL_CREATE" => Event::ChannelCreate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "VOICE_CHANNEL_SELECT" => {
+            Event::VoiceChannelSelect(serde_json::from_slice::<Data<_>>(s)?.data)
+        }
+        "VOICE_STATE_CREATE" => Event::VoiceStateCreate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "VOICE_STATE_UPDATE" => Event::VoiceStateUpdate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "VOICE_STATE_DELETE" => Event::VoiceStateDelete(serde_json::from_slice::<Data<_>>(s)?.data),
+        "VOICE_SETTINGS_UPDATE" => {
+            Event::VoiceSettingsUpdate(serde_json::from_slice::<Data<_>>(s)?.data)
+        }
+        "VOICE_CONNECTION_STATUS" => {
+            Event::VoiceConnectionStatus(serde_json::from_slice::<Data<_>>(s)?.data)
+        }
+        "CAPTURE_SHORTCUT_CHANGE" => {
+            Event::CaptureShortcutChange(serde_json::from_slice::<Data<_>>(s)?.data)
+        }
+        "SPEAKING_START" => Event::SpeakingStart(serde_json::from_slice::<Data<_>>(s)?.data),
+        "SPEAKING_STOP" => Event::SpeakingStop(serde_json::from_slice::<Data<_>>(s)?.data),
+        "MESSAGE_CREATE" => Event::MessageCreate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "MESSAGE_UPDATE" => Event::MessageUpdate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "MESSAGE_DELETE" => Event::MessageDelete(serde_json::from_slice::<Data<_>>(s)?.data),
+        "NOTIFICATION_CREATE" => {
+            Event::NotificationCreate(serde_json::from_slice::<Data<_>>(s)?.data)
+        }
+        "ACTIVITY_JOIN" => Event::ActivityJoin(serde_json::from_slice::<Data<_>>(s)?.data),
+        "ACTIVITY_SPECTATE" => Event::ActivitySpectate(serde_json::from_slice::<Data<_>>(s)?.data),
+        "ACTIVITY_JOIN_REQUEST" => {
+            Event::ActivityJoinRequest(serde_json::from_slice::<Data<_>>(s)?.data)
+        }
+        _ => Event::Unknown {
+            evt: evt.to_string(),
+            data: serde_json::from_slice::<Data<_>>(s)?.data,
+        },
+    })
+}
+
 #[cfg(test)]
 mod tests {
 
     use crate::{
-        command::VoiceSettingsUpdate,
-        voice::{Device, InputSettings, Key, KeyType, ModeSettings, OutputSettings, VoiceMode},
+        command::{RpcErrorCode, VoiceSettingsUpdate},
+        voice::{
+            Device, InputSettingsUpdate, Key, KeyType, ModeSettings, OutputSettingsUpdate,
+            VoiceMode,
+        },
     };
 
     use super::*;
 
     #[test]
+    #[allow(clippy::excessive_precision)]
     fn simple_test() {
         let s = "{
   \"cmd\": \"DISPATCH\",
@@ -152,57 +215,164 @@ mod tests {
 }";
         println!("{}", s);
 
-        let event = parse_response::<()>(s.as_bytes()).unwrap();
+        let event = parse_response::<()>(s.as_bytes(), ParseOptions::default(), None).unwrap();
         assert_eq!(
             event,
-            OutPayload::Event(Event::VoiceSettingsUpdate(VoiceSettingsUpdate {
-                input: Some(InputSettings {
-                    device_id: "default".into(),
-                    volume: 49.803921580314636,
-                    available_devices: vec![
-                        Device {
-                            id: "default".into(),
-                            name: "Default".into(),
-                        },
-                        Device {
-                            id: "Built-in Microphone".into(),
-                            name: "Built-in Microphone".into(),
-                        }
-                    ]
+            OutPayload::Event(
+                Event::VoiceSettingsUpdate(VoiceSettingsUpdate {
+                    input: Some(InputSettingsUpdate {
+                        device_id: "default".into(),
+                        volume: 49.803921580314636,
+                        available_devices: Some(vec![
+                            Device {
+                                id: "default".into(),
+                                name: "Default".into(),
+                            },
+                            Device {
+                                id: "Built-in Microphone".into(),
+                                name: "Built-in Microphone".into(),
+                            }
+                        ])
+                    }),
+                    output: Some(OutputSettingsUpdate {
+                        device_id: "default".into(),
+                        volume: 93.00000071525574,
+                        available_devices: Some(vec![
+                            Device {
+                                id: "default".into(),
+                                name: "Default".into(),
+                            },
+                            Device {
+                                id: "Built-in Output".into(),
+                                name: "Built-in Output".into(),
+                            }
+                        ])
+                    }),
+                    mode: Some(ModeSettings {
+                        r#type: VoiceMode::VoiceActivity,
+                        threshold: -46.92622950819673,
+                        auto_threshold: Some(true),
+                        shortcut: vec![Key {
+                            r#type: KeyType::KeyboardKey,
+                            code: 12,
+                            name: "i".into(),
+                        }],
+                        delay: 98.36065573770492,
+                    }),
+                    automatic_gain_control: Some(false),
+                    echo_cancellation: Some(false),
+                    noise_suppression: Some(false),
+                    qos: Some(false),
+                    silence_warning: Some(false),
+                    deaf: None,
+                    mute: None
                 }),
-                output: Some(OutputSettings {
-                    device_id: "default".into(),
-                    volume: 93.00000071525574,
-                    available_devices: vec![
-                        Device {
-                            id: "default".into(),
-                            name: "Default".into(),
-                        },
-                        Device {
-                            id: "Built-in Output".into(),
-                            name: "Built-in Output".into(),
-                        }
-                    ]
-                }),
-                mode: Some(ModeSettings {
-                    r#type: VoiceMode::VoiceActivity,
-                    threshold: -46.92622950819673,
-                    auto_threshold: Some(true),
-                    shortcut: vec![Key {
-                        r#type: KeyType::KeyboardKey,
-                        code: 12,
-                        name: "i".into(),
-                    }],
-                    delay: 98.36065573770492,
-                }),
-                automatic_gain_control: Some(false),
-                echo_cancellation: Some(false),
-                noise_suppression: Some(false),
-                qos: Some(false),
-                silence_warning: Some(false),
-                deaf: None,
-                mute: None
-            }))
+                None
+            )
         )
     }
+
+    #[test]
+    fn error_codes() {
+        let s = r#"{"cmd":"GET_GUILD","data":{"code":5002,"message":"Request to guild timed out"},"evt":"ERROR","nonce":"1"}"#;
+        let OutPayload::<()>::Error(e) =
+            parse_response(s.as_bytes(), ParseOptions::default(), None).unwrap()
+        else {
+            panic!("Expected an error");
+        };
+        assert_eq!(e.code, RpcErrorCode::GetGuildTimedOut);
+        assert!(e.code.is_retryable());
+
+        let s = r#"{"cmd":"AUTHENTICATE","data":{"code":4009,"message":"Invalid token"},"evt":"ERROR","nonce":"2"}"#;
+        let OutPayload::<()>::Error(e) =
+            parse_response(s.as_bytes(), ParseOptions::default(), None).unwrap()
+        else {
+            panic!("Expected an error");
+        };
+        assert!(e.code.is_auth_error());
+        assert!(!e.code.is_retryable());
+
+        assert_eq!(RpcErrorCode::from(4999), RpcErrorCode::Unknown(4999));
+        assert_eq!(u64::from(RpcErrorCode::Unknown(4999)), 4999);
+    }
+
+    #[test]
+    fn unknown_and_malformed_events() {
+        let unknown = br#"{"cmd":"DISPATCH","data":{"id":"1"},"evt":"LOBBY_UPDATE"}"#;
+        assert_eq!(
+            parse_response::<()>(unknown, ParseOptions::default(), None).unwrap(),
+            OutPayload::Event(
+                Event::Unknown {
+                    evt: "LOBBY_UPDATE".into(),
+                    data: serde_json::json!({ "id": "1" }),
+                },
+                None
+            )
+        );
+
+        let malformed = br#"{"cmd":"DISPATCH","data":{"user_id":5},"evt":"SPEAKING_START"}"#;
+        assert!(matches!(
+            parse_response::<()>(malformed, ParseOptions::default(), None),
+            Err(Error::MalformedEvent { evt, .. }) if evt == "SPEAKING_START"
+        ));
+        let lenient = ParseOptions {
+            lenient: true,
+            keep_raw: false,
+        };
+        let Ok(OutPayload::Event(Event::Malformed { evt, data, .. }, None)) =
+            parse_response::<()>(malformed, lenient, None)
+        else {
+            panic!("Expected a malformed event");
+        };
+        assert_eq!(evt, "SPEAKING_START");
+        assert_eq!(data, serde_json::json!({ "user_id": 5 }));
+
+        let extra =
+            br#"{"cmd":"DISPATCH","data":{"user_id":"5","new":true},"evt":"SPEAKING_STOP"}"#;
+        let keep_raw = ParseOptions {
+            lenient: false,
+            keep_raw: true,
+        };
+        let Ok(OutPayload::Event(Event::SpeakingStop(_), Some(raw))) =
+            parse_response::<()>(extra, keep_raw, None)
+        else {
+            panic!("Expected an event with raw data");
+        };
+        assert_eq!(raw["new"], true);
+    }
+
+    #[test]
+    fn response_mismatch() {
+        let expected = ResponseId {
+            cmd: "SET_ACTIVITY".into(),
+            nonce: Some("3".into()),
+        };
+        let s = br#"{"cmd":"SET_ACTIVITY","data":{},"evt":null,"nonce":"3"}"#;
+        assert!(matches!(
+            parse_response::<serde_json::Value>(s, ParseOptions::default(), Some(&expected)),
+            Ok(OutPayload::CommandResponse(_))
+        ));
+
+        let s = br#"{"cmd":"GET_SELECTED_VOICE_CHANNEL","data":null,"evt":null,"nonce":"3"}"#;
+        let Err(Error::ResponseMismatch { got, .. }) =
+            parse_response::<serde_json::Value>(s, ParseOptions::default(), Some(&expected))
+        else {
+            panic!("Expected a mismatch");
+        };
+        assert_eq!(got.cmd, "GET_SELECTED_VOICE_CHANNEL");
+
+        let s = br#"{"cmd":"SET_ACTIVITY","data":{"code":1000,"message":"?"},"evt":"ERROR","nonce":"2"}"#;
+        let Err(Error::ResponseMismatch { got, .. }) =
+            parse_response::<serde_json::Value>(s, ParseOptions::default(), Some(&expected))
+        else {
+            panic!("Expected a mismatch");
+        };
+        assert_eq!(got.nonce.as_deref(), Some("2"));
+
+        let s = br#"{"cmd":"DISPATCH","data":{"channel_id":null},"evt":"VOICE_CHANNEL_SELECT","nonce":null}"#;
+        assert!(matches!(
+            parse_response::<serde_json::Value>(s, ParseOptions::default(), Some(&expected)),
+            Ok(OutPayload::Event(..))
+        ));
+    }
 }
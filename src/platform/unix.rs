@@ -1,23 +1,48 @@
 use std::{env, io, path::PathBuf};
 
-use crate::ConnectionBuilder;
-use tokio::net::UnixStream;
-
 pub struct UnixConnection;
 
+/// Location of the socket opened by the Discord client
+fn socket_path() -> PathBuf {
+    let tmp = env::var("XDG_RUNTIME_DIR")
+        .or_else(|_| env::var("TMPDIR"))
+        .or_else(|_| match env::temp_dir().to_str() {
+            None => Err("Failed to convert temp_dir"),
+            Some(tmp) => Ok(tmp.to_string()),
+        })
+        .unwrap_or("/tmp".to_string());
+    PathBuf::from(tmp).join("discord-ipc-0")
+}
+
+#[cfg(feature = "tokio")]
 #[async_trait::async_trait]
-impl ConnectionBuilder for UnixConnection {
-    type Socket = UnixStream;
+impl crate::ConnectionBuilder for UnixConnection {
+    type Socket = crate::transport::TokioIo<tokio::net::UnixStream>;
 
     async fn connect() -> io::Result<Self::Socket> {
-        let tmp = env::var("XDG_RUNTIME_DIR")
-            .or_else(|_| env::var("TMPDIR"))
-            .or_else(|_| match env::temp_dir().to_str() {
-                None => Err("Failed to convert temp_dir"),
-                Some(tmp) => Ok(tmp.to_string()),
-            })
-            .unwrap_or("/tmp".to_string());
-        let path = PathBuf::from(tmp).join("discord-ipc-0");
-        UnixStream::connect(path).await
+        tokio::net::UnixStream::connect(socket_path())
+            .await
+            .map(crate::transport::TokioIo)
+    }
+}
+
+#[cfg(all(feature = "futures-io", not(feature = "tokio")))]
+#[async_trait::async_trait]
+impl crate::ConnectionBuilder for UnixConnection {
+    type Socket = crate::transport::FuturesIo<async_io::Async<std::os::unix::net::UnixStream>>;
+
+    async fn connect() -> io::Result<Self::Socket> {
+        async_io::Async::<std::os::unix::net::UnixStream>::connect(socket_path())
+            .await
+            .map(crate::transport::FuturesIo)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl crate::blocking::ConnectionBuilder for UnixConnection {
+    type Socket = std::os::unix::net::UnixStream;
+
+    fn connect() -> io::Result<Self::Socket> {
+        std::os::unix::net::UnixStream::connect(socket_path())
     }
 }
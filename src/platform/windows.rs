@@ -1,14 +1,30 @@
-use crate::ConnectionBuilder;
 use std::io;
-use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
 
 pub struct NamedPipeSocket {}
 
+#[allow(dead_code)]
+const PIPE_NAME: &str = r"\\.\pipe\discord-ipc-0";
+
+#[cfg(feature = "tokio")]
 #[async_trait::async_trait]
-impl ConnectionBuilder for NamedPipeSocket {
-    type Socket = NamedPipeClient;
+impl crate::ConnectionBuilder for NamedPipeSocket {
+    type Socket = crate::transport::TokioIo<tokio::net::windows::named_pipe::NamedPipeClient>;
 
     async fn connect() -> io::Result<Self::Socket> {
-        ClientOptions::new().open(r"\\.\pipe\discord-ipc-0")
+        tokio::net::windows::named_pipe::ClientOptions::new()
+            .open(PIPE_NAME)
+            .map(crate::transport::TokioIo)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl crate::blocking::ConnectionBuilder for NamedPipeSocket {
+    type Socket = std::fs::File;
+
+    fn connect() -> io::Result<Self::Socket> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(PIPE_NAME)
     }
 }
@@ -0,0 +1,291 @@
+//! Combining presence from several sources
+//!
+//! Discord accepts one IPC connection per application, so programs sharing an application ID
+//! send their presence to a single connection, e.g. through the `discord-ipcd` daemon.
+//! [`PresenceSources`] keeps each source's [`PresenceUpdate`] and combines them according to a
+//! [`MergePolicy`].
+//!
+//! [`template`] describes presence in a file instead, filled from the environment & commands.
+
+use serde::{Deserialize, Serialize};
+
+use crate::activity::{Activity, Button};
+
+pub mod template;
+
+/// Presence contributed by a source. Unset fields are left to other sources when merging
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresenceUpdate {
+    /// First line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    /// Second line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// Large image asset key or URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_image: Option<String>,
+    /// Large image hover text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_text: Option<String>,
+    /// Small image asset key or URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_image: Option<String>,
+    /// Small image hover text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_text: Option<String>,
+    /// Buttons, at most two are shown
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<Button>,
+}
+
+impl PresenceUpdate {
+    /// Fill the fields not set in `self` from `other`. Buttons are taken as a whole
+    fn fill_from(&mut self, other: &Self) {
+        fn fill(field: &mut Option<String>, other: &Option<String>) {
+            if field.is_none() {
+                field.clone_from(other);
+            }
+        }
+        fill(&mut self.details, &other.details);
+        fill(&mut self.state, &other.state);
+        if self.large_image.is_none() {
+            self.large_image.clone_from(&other.large_image);
+            self.large_text.clone_from(&other.large_text);
+        }
+        if self.small_image.is_none() {
+            self.small_image.clone_from(&other.small_image);
+            self.small_text.clone_from(&other.small_text);
+        }
+        if self.buttons.is_empty() {
+            self.buttons.clone_from(&other.buttons);
+        }
+    }
+}
+
+impl From<&PresenceUpdate> for Activity {
+    fn from(update: &PresenceUpdate) -> Self {
+        let mut activity = Activity::new();
+        if let Some(details) = &update.details {
+            activity = activity.details(details);
+        }
+        if let Some(state) = &update.state {
+            activity = activity.state(state);
+        }
+        if let Some(image) = &update.large_image {
+            activity = activity.large_image(image, update.large_text.clone());
+        }
+        if let Some(image) = &update.small_image {
+            activity = activity.small_image(image, update.small_text.clone());
+        }
+        for button in update.buttons.iter().take(2) {
+            activity = activity.button(&button.label, &button.url);
+        }
+        activity
+    }
+}
+
+/// How the presence of several sources is combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergePolicy {
+    /// Only the highest priority source is shown
+    Highest,
+    /// The highest priority source is shown, with fields it doesn't set taken from lower
+    /// priority sources
+    #[default]
+    Merge,
+}
+
+/// Presence of several sources, identified by `K`
+///
+/// Higher priorities win; between sources of the same priority, the most recently updated wins.
+#[derive(Debug, Clone)]
+pub struct PresenceSources<K> {
+    policy: MergePolicy,
+    sources: Vec<Source<K>>,
+    updates: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Source<K> {
+    key: K,
+    priority: i32,
+    /// Order of the last update
+    updated: u64,
+    presence: PresenceUpdate,
+}
+
+impl<K: PartialEq> PresenceSources<K> {
+    /// Create an empty set of sources
+    pub fn new(policy: MergePolicy) -> Self {
+        Self {
+            policy,
+            sources: vec![],
+            updates: 0,
+        }
+    }
+
+    /// Set the presence of a source, replacing its previous presence
+    pub fn set(&mut self, key: K, priority: i32, presence: PresenceUpdate) {
+        self.updates += 1;
+        let source = Source {
+            key,
+            priority,
+            updated: self.updates,
+            presence,
+        };
+        match self.sources.iter_mut().find(|s| s.key == source.key) {
+            Some(existing) => *existing = source,
+            None => self.sources.push(source),
+        }
+    }
+
+    /// Remove the presence of a source, returning whether it had one
+    pub fn remove(&mut self, key: &K) -> bool {
+        let len = self.sources.len();
+        self.sources.retain(|s| &s.key != key);
+        self.sources.len() != len
+    }
+
+    /// Whether no source has a presence
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// The combined presence, or `None` if there are no sources
+    pub fn merged(&self) -> Option<PresenceUpdate> {
+        let mut sources: Vec<_> = self.sources.iter().collect();
+        sources.sort_by_key(|s| std::cmp::Reverse((s.priority, s.updated)));
+        let (first, rest) = sources.split_first()?;
+        let mut presence = first.presence.clone();
+        if self.policy == MergePolicy::Merge {
+            for source in rest {
+                presence.fill_from(&source.presence);
+            }
+        }
+        Some(presence)
+    }
+}
+
+/// Request sent to the `discord-ipcd` control socket, one JSON object per line
+///
+/// ```json
+/// {"op": "set", "priority": 10, "details": "Editing main.rs", "large_image": "editor"}
+/// {"op": "clear"}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum ControlRequest {
+    /// Set this connection's presence, replacing its previous presence
+    Set {
+        /// Priority against other connections, higher wins. Defaults to 0
+        #[serde(default)]
+        priority: i32,
+        /// Presence
+        #[serde(flatten)]
+        presence: PresenceUpdate,
+    },
+    /// Remove this connection's presence. Closing the connection does the same
+    Clear,
+}
+
+/// Response to a [`ControlRequest`], one JSON object per line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    /// Whether the request was accepted
+    pub ok: bool,
+    /// Why the request was rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(details: Option<&str>, state: Option<&str>) -> PresenceUpdate {
+        PresenceUpdate {
+            details: details.map(str::to_string),
+            state: state.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_policies() {
+        let mut sources = PresenceSources::new(MergePolicy::Merge);
+        assert_eq!(sources.merged(), None);
+        sources.set("editor", 10, update(Some("Editing"), None));
+        sources.set("music", 0, update(Some("Listening"), Some("Song")));
+        assert_eq!(
+            sources.merged(),
+            Some(update(Some("Editing"), Some("Song")))
+        );
+
+        // Same priority: the latest update wins
+        sources.set("build", 10, update(Some("Building"), None));
+        assert_eq!(
+            sources.merged(),
+            Some(update(Some("Building"), Some("Song")))
+        );
+        assert!(sources.remove(&"build"));
+        assert!(!sources.remove(&"build"));
+        assert_eq!(
+            sources.merged(),
+            Some(update(Some("Editing"), Some("Song")))
+        );
+
+        let mut sources = PresenceSources::new(MergePolicy::Highest);
+        sources.set("editor", 10, update(Some("Editing"), None));
+        sources.set("music", 0, update(Some("Listening"), Some("Song")));
+        assert_eq!(sources.merged(), Some(update(Some("Editing"), None)));
+        sources.remove(&"editor");
+        sources.remove(&"music");
+        assert!(sources.is_empty());
+    }
+
+    #[test]
+    fn images_merge_with_their_text() {
+        let mut sources = PresenceSources::new(MergePolicy::Merge);
+        sources.set(
+            1,
+            0,
+            PresenceUpdate {
+                large_image: Some("music".into()),
+                large_text: Some("Music".into()),
+                ..Default::default()
+            },
+        );
+        sources.set(
+            2,
+            1,
+            PresenceUpdate {
+                large_image: Some("editor".into()),
+                ..Default::default()
+            },
+        );
+        let merged = sources.merged().unwrap();
+        assert_eq!(merged.large_image.as_deref(), Some("editor"));
+        assert_eq!(merged.large_text, None);
+    }
+
+    #[test]
+    fn control_requests() {
+        let request: ControlRequest = serde_json::from_str(
+            r#"{"op":"set","priority":10,"details":"Editing","buttons":[{"label":"a","url":"b"}]}"#,
+        )
+        .unwrap();
+        let ControlRequest::Set { priority, presence } = request else {
+            panic!("Expected set");
+        };
+        assert_eq!(priority, 10);
+        assert_eq!(presence.details.as_deref(), Some("Editing"));
+        assert_eq!(presence.buttons.len(), 1);
+        assert_eq!(
+            serde_json::from_str::<ControlRequest>(r#"{"op":"clear"}"#).unwrap(),
+            ControlRequest::Clear
+        );
+    }
+}
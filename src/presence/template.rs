@@ -0,0 +1,517 @@
+//! Presence described by a file
+//!
+//! A [`PresenceTemplate`] is an [`Activity`], written as JSON or as TOML with the `toml` feature,
+//! whose strings can contain placeholders:
+//!
+//! - `{env.NAME}`: the environment variable `NAME`
+//! - `{cmd.NAME}`: the trimmed output of the command `NAME` from `commands`, re-run every
+//!   `interval` seconds
+//! - `{var.NAME}`: a value set with [`PresenceVars::set`]
+//!
+//! `{{` and `}}` are literal braces. Missing values render empty and rendered strings are trimmed;
+//! strings that render empty are left out, and so are objects left without fields. Timestamps are
+//! sent as numbers.
+//!
+//! ```toml
+//! interval = 15
+//!
+//! [commands]
+//! song = "playerctl metadata title"
+//!
+//! [activity]
+//! type = 2
+//! details = "Listening to {cmd.song}"
+//! state = "on {env.HOSTNAME}"
+//! buttons = [{ label = "Profile", url = "https://example.com/{env.USER}" }]
+//!
+//! [activity.assets]
+//! large_image = "music"
+//!
+//! [activity.timestamps]
+//! start = "{var.started}"
+//! ```
+//!
+//! [`PresenceFile`] renders a template file, reloading it when it changes, and
+//! [`PresenceFile::run`] keeps a client's activity up to date.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{activity::Activity, Client, Connection, Error, Result};
+
+/// How often [`PresenceFile::run`] checks for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long [`PresenceFile::run`] lets a command run before killing it
+#[cfg(feature = "tokio")]
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_interval() -> u64 {
+    15
+}
+
+/// Contents of a presence file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PresenceTemplate {
+    /// Seconds between runs of the commands. Defaults to 15
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Shell commands filling `{cmd.NAME}` placeholders, by name
+    #[serde(default)]
+    pub commands: BTreeMap<String, String>,
+    /// The activity, with placeholders
+    pub activity: Value,
+}
+
+impl PresenceTemplate {
+    /// Parse a JSON template
+    pub fn from_json(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Parse a TOML template
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(|e| Error::InvalidTemplate(e.to_string()))
+    }
+
+    /// Load a template from `path`, as TOML if it has a `.toml` extension and as JSON otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            #[cfg(feature = "toml")]
+            return Self::from_toml(&s);
+            #[cfg(not(feature = "toml"))]
+            return Err(Error::InvalidTemplate(
+                "TOML templates need the `toml` feature".to_string(),
+            ));
+        }
+        Self::from_json(&s)
+    }
+
+    /// Render the activity, looking up placeholders with `lookup(source, name)`
+    fn render(&self, lookup: impl Fn(&str, &str) -> Option<String>) -> Result<Activity> {
+        let mut activity = render_value(&self.activity, &lookup)?.unwrap_or(Value::Null);
+        if let Some(Value::Object(timestamps)) = activity.get_mut("timestamps") {
+            for timestamp in timestamps.values_mut() {
+                if let Some(n) = timestamp.as_str().and_then(|s| s.parse::<u64>().ok()) {
+                    *timestamp = Value::from(n);
+                }
+            }
+        }
+        serde_json::from_value(activity).map_err(|e| Error::InvalidTemplate(e.to_string()))
+    }
+}
+
+/// Render the strings in `value`, returning `None` if it rendered empty
+fn render_value(
+    value: &Value,
+    lookup: &impl Fn(&str, &str) -> Option<String>,
+) -> Result<Option<Value>> {
+    Ok(match value {
+        Value::String(s) => render_str(s, lookup)?,
+        Value::Array(values) => {
+            let mut rendered = Vec::with_capacity(values.len());
+            for value in values {
+                rendered.extend(render_value(value, lookup)?);
+            }
+            Some(Value::Array(rendered))
+        }
+        Value::Object(map) => {
+            let mut rendered = Map::new();
+            for (key, value) in map {
+                if let Some(value) = render_value(value, lookup)? {
+                    rendered.insert(key.clone(), value);
+                }
+            }
+            (!rendered.is_empty()).then_some(Value::Object(rendered))
+        }
+        value => Some(value.clone()),
+    })
+}
+
+fn render_str(
+    template: &str,
+    lookup: &impl Fn(&str, &str) -> Option<String>,
+) -> Result<Option<Value>> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..i]);
+        let brace = &rest[i..i + 1];
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            rendered.push_str(brace);
+            rest = after;
+            continue;
+        }
+        let key = match (brace, rest.split_once('}')) {
+            ("{", Some((key, after))) => {
+                rest = after;
+                key
+            }
+            _ => {
+                return Err(Error::InvalidTemplate(format!(
+                    "unmatched `{brace}` in {template:?}"
+                )))
+            }
+        };
+        let (source, name) = key
+            .split_once('.')
+            .filter(|(source, _)| ["env", "cmd", "var"].contains(source))
+            .ok_or_else(|| Error::InvalidTemplate(format!("unknown placeholder {{{key}}}")))?;
+        rendered.push_str(&lookup(source, name).unwrap_or_default());
+    }
+    rendered.push_str(rest);
+
+    let rendered = rendered.trim();
+    if rendered.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Value::String(rendered.to_string())))
+}
+
+/// Handle for setting `{var.NAME}` values of a [`PresenceFile`], from any thread
+#[derive(Debug, Clone, Default)]
+pub struct PresenceVars(Arc<Mutex<HashMap<String, String>>>);
+
+impl PresenceVars {
+    /// Set a value, replacing its previous value
+    pub fn set(&self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.lock().unwrap().insert(name.into(), value.into());
+    }
+
+    /// Remove a value, so that it renders empty
+    pub fn remove(&self, name: &str) {
+        self.0.lock().unwrap().remove(name);
+    }
+}
+
+/// A presence file, re-rendered when the file or its inputs change
+#[derive(Debug)]
+pub struct PresenceFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    template: PresenceTemplate,
+    vars: PresenceVars,
+    /// Command outputs, and when the commands last ran
+    outputs: HashMap<String, String>,
+    last_run: Option<Instant>,
+    current: Option<Activity>,
+}
+
+impl PresenceFile {
+    /// Load the template at `path`, see [`PresenceTemplate::load`]
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let modified = std::fs::metadata(&path)?.modified().ok();
+        Ok(Self {
+            template: PresenceTemplate::load(&path)?,
+            path,
+            modified,
+            vars: PresenceVars::default(),
+            outputs: HashMap::new(),
+            last_run: None,
+            current: None,
+        })
+    }
+
+    /// Handle for setting `{var.NAME}` values
+    pub fn vars(&self) -> PresenceVars {
+        self.vars.clone()
+    }
+
+    /// The last rendered activity
+    pub fn current(&self) -> Option<&Activity> {
+        self.current.as_ref()
+    }
+
+    /// Reload the file if it changed and run the commands if they're due, then render the
+    /// activity. Returns the activity if it differs from the last one rendered
+    ///
+    /// A file that fails to reload is logged and the previous template is kept, so that a file
+    /// saved half-edited doesn't interrupt the presence. Commands that fail render empty.
+    ///
+    /// Commands run on the calling thread. [`run`](Self::run) runs them in the background
+    /// instead when used within a tokio runtime.
+    pub fn poll(&mut self) -> Result<Option<Activity>> {
+        self.reload();
+        if self.commands_due() {
+            self.outputs = self
+                .template
+                .commands
+                .iter()
+                .map(|(name, command)| (name.clone(), run_command(command)))
+                .collect();
+        }
+        self.render()
+    }
+
+    /// [`poll`](Self::poll), without blocking the runtime on commands
+    async fn poll_async(&mut self) -> Result<Option<Activity>> {
+        self.reload();
+        if self.commands_due() {
+            let mut outputs = HashMap::new();
+            for (name, command) in &self.template.commands {
+                outputs.insert(name.clone(), run_command_async(command).await);
+            }
+            self.outputs = outputs;
+        }
+        self.render()
+    }
+
+    /// Reload the file if it changed
+    fn reload(&mut self) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified != self.modified {
+            self.modified = modified;
+            match PresenceTemplate::load(&self.path) {
+                Ok(template) => {
+                    debug!("Reloaded {}", self.path.display());
+                    self.template = template;
+                    self.last_run = None;
+                }
+                Err(e) => warn!("Failed to reload {}: {e}", self.path.display()),
+            }
+        }
+    }
+
+    /// Check whether the commands should run again, marking them as run if they should
+    fn commands_due(&mut self) -> bool {
+        let interval = Duration::from_secs(self.template.interval);
+        let due = self.last_run.is_none_or(|run| run.elapsed() >= interval);
+        if due {
+            self.last_run = Some(Instant::now());
+        }
+        due
+    }
+
+    /// Render the activity, returning it if it differs from the last one rendered
+    fn render(&mut self) -> Result<Option<Activity>> {
+        let vars = self.vars.0.lock().unwrap().clone();
+        let activity = self.template.render(|source, name| match source {
+            "env" => std::env::var(name).ok(),
+            "cmd" => self.outputs.get(name).cloned(),
+            _ => vars.get(name).cloned(),
+        })?;
+        if self.current.as_ref() == Some(&activity) {
+            return Ok(None);
+        }
+        self.current = Some(activity.clone());
+        Ok(Some(activity))
+    }
+
+    /// Keep the activity of `client` in sync with the file until the connection fails
+    ///
+    /// Templates that fail to render, and activities Discord rejects, are logged. Within a tokio
+    /// runtime, commands run as child processes without blocking the runtime, and are killed if
+    /// they take longer than 5 seconds.
+    pub async fn run<C: Connection>(&mut self, client: &mut Client<C>) -> Result<()> {
+        loop {
+            match self.poll_async().await {
+                Ok(Some(activity)) => match client.set_activity(activity).await {
+                    Ok(_) => (),
+                    Err(Error::Discord(e)) => warn!("Discord rejected the activity: {e:?}"),
+                    Err(e) => return Err(e),
+                },
+                Ok(None) => (),
+                Err(e) => warn!("Failed to render {}: {e}", self.path.display()),
+            }
+            crate::transport::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Shell command running `command`
+fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    let mut shell = Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    shell.arg(command);
+    shell
+}
+
+/// Run a shell command, returning its trimmed output
+fn run_command(command: &str) -> String {
+    command_output(command, shell(command).output())
+}
+
+/// Run a shell command without blocking, on tokio if used within a tokio runtime
+async fn run_command_async(command: &str) -> String {
+    #[cfg(feature = "tokio")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        let mut child = tokio::process::Command::from(shell(command));
+        child.kill_on_drop(true);
+        return match tokio::time::timeout(COMMAND_TIMEOUT, child.output()).await {
+            Ok(output) => command_output(command, output),
+            Err(_) => {
+                warn!("`{command}` timed out after {COMMAND_TIMEOUT:?}");
+                String::new()
+            }
+        };
+    }
+    run_command(command)
+}
+
+/// Trimmed output of a command, empty if it failed
+fn command_output(command: &str, output: std::io::Result<Output>) -> String {
+    match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        Ok(output) => {
+            warn!("`{command}` failed with {}", output.status);
+            String::new()
+        }
+        Err(e) => {
+            warn!("Failed to run `{command}`: {e}");
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn lookup(source: &str, name: &str) -> Option<String> {
+        match (source, name) {
+            ("var", "song") => Some("Song".into()),
+            ("var", "started") => Some("1700000000".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn placeholders() {
+        let render = |s: &str| render_str(s, &lookup);
+        assert_eq!(render("{var.song}").unwrap(), Some(json!("Song")));
+        assert_eq!(
+            render("Listening to {var.song} {{live}}").unwrap(),
+            Some(json!("Listening to Song {live}"))
+        );
+        assert_eq!(render("{var.started}").unwrap(), Some(json!("1700000000")));
+        assert_eq!(render(" {var.missing} ").unwrap(), None);
+        assert_eq!(render("{var.missing}").unwrap(), None);
+        assert!(render("{nope.x}").is_err());
+        assert!(render("{var.song").is_err());
+        assert!(render("}").is_err());
+    }
+
+    #[test]
+    fn renders_activity() {
+        let template = PresenceTemplate::from_json(
+            r#"{
+                "commands": { "song": "echo Song" },
+                "activity": {
+                    "type": 2,
+                    "details": "Listening to {var.song}",
+                    "state": "{var.missing}",
+                    "timestamps": { "start": "{var.started}000" },
+                    "assets": { "small_image": "{var.missing}" },
+                    "buttons": [{ "label": "Profile", "url": "https://example.com" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(template.interval, 15);
+        let activity = template.render(lookup).unwrap();
+        assert_eq!(
+            serde_json::to_value(&activity).unwrap(),
+            json!({
+                "type": 2,
+                "details": "Listening to Song",
+                "timestamps": { "start": 1700000000000u64, "end": null },
+                "buttons": [{ "label": "Profile", "url": "https://example.com" }]
+            })
+        );
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_templates() {
+        let template = PresenceTemplate::from_toml(
+            r#"
+            interval = 5
+
+            [commands]
+            song = "playerctl metadata title"
+
+            [activity]
+            details = "Listening to {cmd.song}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(template.interval, 5);
+        assert_eq!(template.commands["song"], "playerctl metadata title");
+        assert_eq!(
+            template.activity,
+            json!({ "details": "Listening to {cmd.song}" })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reloads_on_change() {
+        let path = std::env::temp_dir().join(format!("discord-ipc-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{ "commands": { "host": "echo host" }, "activity": { "state": "{cmd.host} {var.x}" } }"#,
+        )
+        .unwrap();
+        let mut file = PresenceFile::open(&path).unwrap();
+        assert_eq!(file.poll().unwrap(), Some(Activity::new().state("host")));
+        assert_eq!(file.poll().unwrap(), None);
+
+        file.vars().set("x", "1");
+        assert_eq!(file.poll().unwrap(), Some(Activity::new().state("host 1")));
+
+        // Force a different modification time, file systems may have coarse timestamps
+        file.modified = None;
+        std::fs::write(&path, r#"{ "activity": { "details": "{var.x}" } }"#).unwrap();
+        assert_eq!(file.poll().unwrap(), Some(Activity::new().details("1")));
+
+        file.modified = None;
+        std::fs::write(&path, "{ half written").unwrap();
+        assert_eq!(file.poll().unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn commands_dont_block_the_runtime() {
+        let path =
+            std::env::temp_dir().join(format!("discord-ipc-commands-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{ "commands": { "slow": "sleep 1; echo slow" }, "activity": { "state": "{cmd.slow}" } }"#,
+        )
+        .unwrap();
+        let mut file = PresenceFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        tokio::select! {
+            _ = file.poll_async() => panic!("The command takes a second"),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => (),
+        }
+        assert_eq!(run_command_async("echo fast").await, "fast");
+    }
+}
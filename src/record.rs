@@ -0,0 +1,375 @@
+//! Recording & replaying IPC sessions, for debugging
+//!
+//! A [`Recorder`] set with [`ClientBuilder::record`](crate::ClientBuilder::record) writes every
+//! frame sent and received to a JSON-lines file, with tokens redacted. [`Replay`] feeds a
+//! recording back into a client as a [`Connection`], so that a session can be reproduced without
+//! Discord running.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use chrono::{DateTime, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    codec::{Frame, FrameDecoder, OpCode},
+    Connection,
+};
+
+/// Fields replaced with [`REDACTED`] in recordings
+pub const REDACTED_FIELDS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "rpc_token",
+    "client_secret",
+];
+
+/// Value recorded in place of redacted fields
+pub const REDACTED: &str = "[redacted]";
+
+/// Direction of a recorded frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Sent to Discord
+    Sent,
+    /// Received from Discord
+    Received,
+}
+
+/// A line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Direction of the frame
+    pub direction: Direction,
+    /// Frame opcode
+    pub opcode: u32,
+    /// When the frame was sent or received
+    pub timestamp: DateTime<Utc>,
+    /// JSON payload. Payloads that aren't valid JSON are recorded as a string
+    pub payload: Value,
+}
+
+/// Writes frames to a JSON-lines recording
+pub struct Recorder {
+    writer: Box<dyn Write + Send>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Record to a new file at `path`, replacing an existing file
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Record to `writer`
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+        }
+    }
+
+    /// Record a frame. Failures are logged rather than interrupting the connection
+    pub(crate) fn record(&mut self, direction: Direction, opcode: u32, payload: &[u8]) {
+        let mut payload = serde_json::from_slice(payload)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()));
+        redact(&mut payload);
+        let frame = RecordedFrame {
+            direction,
+            opcode,
+            timestamp: Utc::now(),
+            payload,
+        };
+        let res = serde_json::to_writer(&mut self.writer, &frame)
+            .map_err(io::Error::from)
+            .and_then(|()| self.writer.write_all(b"\n"))
+            .and_then(|()| self.writer.flush());
+        if let Err(e) = res {
+            warn!("Failed to record frame: {e}");
+        }
+    }
+}
+
+/// Replace the values of [`REDACTED_FIELDS`] anywhere in `value`, along with the OAuth
+/// authorization code of an `AUTHORIZE` response, which can be exchanged for a token
+pub fn redact(value: &mut Value) {
+    if value["cmd"] == "AUTHORIZE" {
+        if let Some(code) = value
+            .pointer_mut("/data/code")
+            .filter(|code| !code.is_null())
+        {
+            *code = Value::String(REDACTED.to_string());
+        }
+    }
+    redact_fields(value);
+}
+
+fn redact_fields(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if REDACTED_FIELDS.contains(&key.as_str()) && !value.is_null() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_fields(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_fields),
+        _ => (),
+    }
+}
+
+/// A recorded session, played back as a [`Connection`]
+///
+/// Received frames are read back in order. Frames written by the client are matched with the
+/// recorded sent frames, and the nonces of recorded responses are rewritten to the nonces the
+/// client used, so that responses still match their commands. Once the recording is exhausted,
+/// reads return EOF.
+#[derive(Debug)]
+pub struct Replay {
+    frames: VecDeque<RecordedFrame>,
+    /// Encoded frames ready to be read
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    /// Frames written by the client, not yet matched with a recorded frame
+    written: FrameDecoder,
+    unmatched: VecDeque<Frame>,
+    /// Recorded nonces, mapped to the client's nonces
+    nonces: HashMap<String, Value>,
+}
+
+impl Replay {
+    /// Replay the recording at `path`
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Replay a recording read from `reader`
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut frames = VecDeque::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                frames.push_back(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Self::new(frames))
+    }
+
+    /// Replay a list of frames
+    pub fn new(frames: impl IntoIterator<Item = RecordedFrame>) -> Self {
+        Self {
+            frames: frames.into_iter().collect(),
+            read_buf: vec![],
+            read_pos: 0,
+            written: FrameDecoder::default(),
+            unmatched: VecDeque::new(),
+            nonces: HashMap::new(),
+        }
+    }
+
+    /// Encode the next received frame into `read_buf`, returning false at the end of the
+    /// recording
+    fn next_received(&mut self) -> bool {
+        while let Some(frame) = self.frames.pop_front() {
+            match frame.direction {
+                Direction::Sent => match self.unmatched.pop_front() {
+                    Some(sent) => {
+                        let nonce = sent.json::<Value>().ok().map(|v| v["nonce"].clone());
+                        if let (Some(recorded), Some(nonce)) =
+                            (frame.payload["nonce"].as_str(), nonce)
+                        {
+                            self.nonces.insert(recorded.to_string(), nonce);
+                        }
+                    }
+                    None => debug!("Client didn't send recorded frame {:?}", frame.payload),
+                },
+                Direction::Received => {
+                    let mut payload = frame.payload;
+                    if let Some(nonce) = payload["nonce"].as_str().and_then(|n| self.nonces.get(n))
+                    {
+                        payload["nonce"] = nonce.clone();
+                    }
+                    let payload = match payload {
+                        Value::String(s) => s.into_bytes(),
+                        payload => serde_json::to_vec(&payload).expect("Values serialize"),
+                    };
+                    let Ok(opcode) = OpCode::try_from(frame.opcode) else {
+                        // Written as is, the client reports the unknown opcode
+                        self.read_buf.extend_from_slice(&frame.opcode.to_le_bytes());
+                        self.read_buf
+                            .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                        self.read_buf.extend_from_slice(&payload);
+                        return true;
+                    };
+                    Frame { opcode, payload }.encode(&mut self.read_buf);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl Connection for Replay {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.read_pos == self.read_buf.len() {
+            self.read_buf.clear();
+            self.read_pos = 0;
+            if !self.next_received() {
+                return Poll::Ready(Ok(0));
+            }
+        }
+        let n = buf.len().min(self.read_buf.len() - self.read_pos);
+        buf[..n].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.written.extend(buf);
+        while let Some(frame) = self
+            .written
+            .next_frame()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        {
+            self.unmatched.push_back(frame);
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn redacts_tokens() {
+        let mut payload = json!({
+            "cmd": "AUTHENTICATE",
+            "args": { "access_token": "secret" },
+            "data": [{ "refresh_token": "secret", "rpc_token": null, "name": "test" }]
+        });
+        redact(&mut payload);
+        assert_eq!(
+            payload,
+            json!({
+                "cmd": "AUTHENTICATE",
+                "args": { "access_token": REDACTED },
+                "data": [{ "refresh_token": REDACTED, "rpc_token": null, "name": "test" }]
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_authorization_code() {
+        let path = std::env::temp_dir().join(format!(
+            "discord-ipc-authorize-{}.jsonl",
+            std::process::id()
+        ));
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(
+            Direction::Received,
+            OpCode::FRAME as u32,
+            br#"{"cmd":"AUTHORIZE","evt":null,"data":{"code":"secret"},"nonce":"1"}"#,
+        );
+        recorder.record(
+            Direction::Sent,
+            OpCode::FRAME as u32,
+            br#"{"client_id":"42","client_secret":"secret","code":"not a secret"}"#,
+        );
+        drop(recorder);
+        let replay = Replay::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let payloads: Vec<_> = replay.frames.into_iter().map(|f| f.payload).collect();
+        assert_eq!(
+            payloads,
+            [
+                json!({ "cmd": "AUTHORIZE", "evt": null, "data": { "code": REDACTED }, "nonce": "1" }),
+                json!({ "client_id": "42", "client_secret": REDACTED, "code": "not a secret" }),
+            ]
+        );
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn record_and_replay() {
+        use crate::{
+            mock::{tokio_socket, MockDiscord},
+            Client, Event,
+        };
+
+        let path = std::env::temp_dir().join(format!("discord-ipc-{}.jsonl", std::process::id()));
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.write_frame(OpCode::PING, json!({ "nonce": 1 }));
+            discord.expect_command("SET_ACTIVITY");
+            assert_eq!(discord.read_frame().0, OpCode::PONG as u32);
+            discord.dispatch("VOICE_CHANNEL_SELECT", json!({ "channel_id": null }));
+            discord.respond("SET_ACTIVITY", json!({ "name": "test", "type": 0 }));
+        });
+        let mut client = Client::new(42)
+            .record(Recorder::create(&path).unwrap())
+            .connect_with(tokio_socket(socket))
+            .await
+            .unwrap();
+        client.set_activity("Testing").await.unwrap();
+        discord.join().unwrap();
+        drop(client);
+
+        let replay = Replay::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let directions: Vec<_> = replay.frames.iter().map(|f| f.direction).collect();
+        assert_eq!(
+            directions,
+            [
+                Direction::Sent,
+                Direction::Received,
+                Direction::Sent,
+                Direction::Received,
+                Direction::Sent,
+                Direction::Received,
+                Direction::Received,
+            ]
+        );
+        let mut client = Client::new(42).connect_with(replay).await.unwrap();
+        assert_eq!(client.user().username, "user");
+        client.set_activity("Testing").await.unwrap();
+        assert!(matches!(
+            client.event().await.unwrap(),
+            Event::VoiceChannelSelect(_)
+        ));
+        assert!(matches!(
+            client.event().await,
+            Err(crate::Error::PipeClosed)
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+//! Adapters from async runtime IO traits to [`Connection`]
+//!
+//! The client itself doesn't depend on a runtime: it only reads and writes through
+//! [`Connection`]. Wrap a stream in [`TokioIo`] (`tokio` feature) or [`FuturesIo`] (`futures-io`
+//! feature, for async-std and smol) and pass it to
+//! [`ClientBuilder::connect_with`](crate::ClientBuilder::connect_with).
+//!
+//! OAuth token requests are made with `reqwest`, which needs a tokio runtime. Clients without a
+//! secret don't make any requests.
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+use std::io;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Condvar, Mutex, OnceLock},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+use crate::Connection;
+
+/// Wait for `duration` on the current runtime's timer. Used for timeouts
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        tokio::time::sleep(duration).await;
+        return;
+    }
+    #[cfg(feature = "futures-io")]
+    {
+        async_io::Timer::after(duration).await;
+        return;
+    }
+    #[allow(unreachable_code)]
+    ThreadTimer::new(duration).await
+}
+
+/// Timers waiting on the timer thread, woken in deadline order
+#[derive(Default)]
+struct Timers {
+    next_id: u64,
+    waiting: BTreeMap<(Instant, u64), Waker>,
+}
+
+/// Timers shared by every [`ThreadTimer`], along with the condition the timer thread waits on
+fn timers() -> &'static (Mutex<Timers>, Condvar) {
+    static TIMERS: OnceLock<(Mutex<Timers>, Condvar)> = OnceLock::new();
+    TIMERS.get_or_init(|| {
+        thread::Builder::new()
+            .name("discord-ipc-timer".into())
+            .spawn(run_timers)
+            .expect("Timer thread starts");
+        Default::default()
+    })
+}
+
+/// Wake each timer once its deadline passes, sleeping until the next deadline
+fn run_timers() {
+    let (timers, changed) = timers();
+    let mut state = timers.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        while let Some(entry) = state.waiting.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            expired.push(entry.remove());
+        }
+        if !expired.is_empty() {
+            // Woken tasks may start new timers
+            drop(state);
+            expired.into_iter().for_each(Waker::wake);
+            state = timers.lock().unwrap();
+            continue;
+        }
+        state = match state.waiting.first_key_value() {
+            Some((&(deadline, _), _)) => changed.wait_timeout(state, deadline - now).unwrap().0,
+            None => changed.wait(state).unwrap(),
+        };
+    }
+}
+
+/// Timer for when no runtime timer is available. Every timer is waited for by a single
+/// background thread, started the first time a timer is used. Dropping a timer cancels it
+struct ThreadTimer {
+    deadline: Instant,
+    id: u64,
+}
+
+impl ThreadTimer {
+    fn new(duration: Duration) -> Self {
+        let mut state = timers().0.lock().unwrap();
+        state.next_id += 1;
+        Self {
+            deadline: Instant::now() + duration,
+            id: state.next_id,
+        }
+    }
+}
+
+impl Future for ThreadTimer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let (timers, changed) = timers();
+        let mut state = timers.lock().unwrap();
+        state
+            .waiting
+            .insert((self.deadline, self.id), cx.waker().clone());
+        changed.notify_one();
+        Poll::Pending
+    }
+}
+
+impl Drop for ThreadTimer {
+    fn drop(&mut self) {
+        let mut state = timers().0.lock().unwrap();
+        state.waiting.remove(&(self.deadline, self.id));
+    }
+}
+
+/// Adapter for streams implementing tokio's `AsyncRead` & `AsyncWrite`
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TokioIo<T>(pub T);
+
+#[cfg(feature = "tokio")]
+impl<T> Connection for TokioIo<T>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        std::task::ready!(Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+/// Adapter for streams implementing `futures-io`'s `AsyncRead` & `AsyncWrite`, e.g. async-std
+/// and smol sockets
+#[cfg(feature = "futures-io")]
+#[derive(Debug)]
+pub struct FuturesIo<T>(pub T);
+
+#[cfg(feature = "futures-io")]
+impl<T> Connection for FuturesIo<T>
+where
+    T: futures_io::AsyncRead + futures_io::AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+#[cfg(all(test, unix, any(feature = "tokio", feature = "futures-io")))]
+mod tests {
+    use serde_json::json;
+
+    use crate::mock::MockDiscord;
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_connection() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.expect_command("SET_ACTIVITY");
+            discord.respond("SET_ACTIVITY", json!({ "name": "test", "type": 0 }));
+        });
+        let mut client = crate::mock::client(socket).await;
+        client.set_activity("Testing").await.unwrap();
+        discord.join().unwrap();
+    }
+
+    /// No tokio runtime is running here
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn futures_io_connection() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            discord.expect_command("SET_ACTIVITY");
+            discord.dispatch("VOICE_CHANNEL_SELECT", json!({ "channel_id": null }));
+            discord.respond("SET_ACTIVITY", json!({ "name": "test", "type": 0 }));
+        });
+        futures_lite::future::block_on(async {
+            let socket = async_io::Async::new(socket).unwrap();
+            let mut client = crate::Client::new(42)
+                .connect_with(super::FuturesIo(socket))
+                .await
+                .unwrap();
+            client.set_activity("Testing").await.unwrap();
+            assert!(matches!(
+                client.event().await.unwrap(),
+                crate::Event::VoiceChannelSelect(_)
+            ));
+        });
+        discord.join().unwrap();
+    }
+
+    #[test]
+    fn thread_timers() {
+        use std::time::{Duration, Instant};
+
+        use futures_lite::future;
+
+        use super::{timers, ThreadTimer};
+
+        let start = Instant::now();
+        future::block_on(future::or(
+            ThreadTimer::new(Duration::from_secs(60)),
+            ThreadTimer::new(Duration::from_millis(20)),
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_secs(60));
+        // The longer timer was cancelled when dropped
+        assert!(timers().0.lock().unwrap().waiting.is_empty());
+    }
+}
@@ -1,26 +1,108 @@
 //! Voice Channel Details
 
-use chrono::prelude::*;
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::{
-    channel::{GuildMember, PartialUser},
-    discord::Snowflake,
+    channel::PartialUser,
+    command::{Command, SetVoiceSettings},
+    discord::{Snowflake, UnixTimestamp},
 };
 
-/// Volume level
+mod control;
+mod devices;
+mod monitor;
+mod roster;
+
+pub use control::{ShortcutCapture, VoiceControl, VoiceInputMode};
+pub use devices::{DeviceChange, DeviceKind, DeviceMatcher, DeviceWatcher};
+pub use monitor::{ConnectionAlert, ConnectionMonitor, PingStats};
+pub use roster::{RosterChange, RosterMember, VoiceRoster};
+
+/// Volume of another user, 0 to 200 where 100 is unchanged
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(transparent)]
-pub struct Volume(pub u8);
+#[serde(from = "u8", into = "u8")]
+pub struct Volume(u8);
+
+impl Volume {
+    /// Loudest volume Discord accepts
+    pub const MAX: u8 = 200;
+
+    /// Volume level, clamped to [`Volume::MAX`]
+    pub fn new(volume: u8) -> Self {
+        Self(volume.min(Self::MAX))
+    }
+
+    /// Volume level
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for Volume {
+    fn from(volume: u8) -> Self {
+        Self::new(volume)
+    }
+}
+
+impl From<Volume> for u8 {
+    fn from(volume: Volume) -> Self {
+        volume.0
+    }
+}
 
 /// Volume Pan (left/right balance)
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PanRepr")]
 pub struct Pan {
     /// Left balance
-    pub left: f32,
+    left: f32,
+    /// Right balance
+    right: f32,
+}
+
+impl Pan {
+    /// Balance from the volume of each side, clamped to 0.0 to 1.0
+    pub fn new(left: f32, right: f32) -> Self {
+        Self {
+            left: clamp(left, 0.0, 1.0),
+            right: clamp(right, 0.0, 1.0),
+        }
+    }
+
+    /// Left balance
+    pub fn left(self) -> f32 {
+        self.left
+    }
+
     /// Right balance
-    pub right: f32,
+    pub fn right(self) -> f32 {
+        self.right
+    }
+}
+
+/// Balance as sent, clamped by [`Pan::new`]
+#[derive(Deserialize)]
+struct PanRepr {
+    left: f32,
+    right: f32,
+}
+
+impl From<PanRepr> for Pan {
+    fn from(pan: PanRepr) -> Self {
+        Self::new(pan.left, pan.right)
+    }
+}
+
+/// Clamp `value` between `min` & `max`, treating NaN as `min`
+fn clamp(value: f32, min: f32, max: f32) -> f32 {
+    if value.is_nan() {
+        min
+    } else {
+        value.clamp(min, max)
+    }
 }
 
 /// Details of a Vendor or Model
@@ -102,6 +184,30 @@ pub struct OutputSettings {
     pub available_devices: Vec<Device>,
 }
 
+/// Input settings in a [`VoiceSettingsUpdate`](crate::command::VoiceSettingsUpdate)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputSettingsUpdate {
+    /// Device to use
+    pub device_id: String,
+    /// Input Volume
+    pub volume: f32,
+    /// List of available devices, if the update includes it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_devices: Option<Vec<Device>>,
+}
+
+/// Output settings in a [`VoiceSettingsUpdate`](crate::command::VoiceSettingsUpdate)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputSettingsUpdate {
+    /// Device to use
+    pub device_id: String,
+    /// Output Volume
+    pub volume: f32,
+    /// List of available devices, if the update includes it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_devices: Option<Vec<Device>>,
+}
+
 /// Voice Mode Settings
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ModeSettings {
@@ -152,53 +258,675 @@ pub enum KeyType {
     GamepadButton = 3,
 }
 
-/// Current Voice State
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// State of the connection to the voice server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VoiceConnectionState {
+    /// Not in a voice channel
+    Disconnected,
+    /// Waiting for a voice server
+    AwaitingEndpoint,
+    /// Authenticating with the voice server
+    Authenticating,
+    /// Connecting to the voice server
+    Connecting,
+    /// Connected to the voice server
+    Connected,
+    /// Connected to the voice server, but voice was disconnected
+    VoiceDisconnected,
+    /// Connecting voice
+    VoiceConnecting,
+    /// Voice is connected
+    VoiceConnected,
+    /// The voice server can't be reached
+    NoRoute,
+    /// Checking the connection to the voice server
+    IceChecking,
+    /// State not known to this library
+    #[serde(other)]
+    Unknown,
+}
+
+/// Round trip time to the voice server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "PingRepr")]
+pub struct Ping {
+    /// When the ping was measured, if sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<UnixTimestamp>,
+    /// Milliseconds
+    pub value: u64,
+}
+
+/// Pings are sent either as plain numbers or with the time they were measured
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PingRepr {
+    Value(u64),
+    Timed {
+        time: Option<UnixTimestamp>,
+        value: u64,
+    },
+}
+
+impl From<PingRepr> for Ping {
+    fn from(ping: PingRepr) -> Self {
+        match ping {
+            PingRepr::Value(value) => Self { time: None, value },
+            PingRepr::Timed { time, value } => Self { time, value },
+        }
+    }
+}
+
+/// Voice state of a member of a voice channel, as sent in a channel's `voice_states` and in
+/// `VOICE_STATE_*` events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VoiceState {
-    /// TODO
-    pub guild_id: Option<Snowflake>,
-    /// TODO
-    pub channel_id: Option<Snowflake>,
-    /// TODO
-    pub user_id: Option<Snowflake>,
-    /// TODO
-    pub member: Option<GuildMember>,
-    /// TODO
-    pub user: Option<PartialUser>,
-    /// TODO
-    pub session_id: Option<Snowflake>,
-    /// TODO
+    /// Server nickname
     #[serde(default)]
-    pub deaf: bool,
-    /// TODO
+    pub nick: Option<String>,
+    /// Muted for the current user only
     #[serde(default)]
     pub mute: bool,
-    /// TODO
-    #[serde(default)]
-    pub self_deaf: bool,
-    /// TODO
+    /// Volume set by the current user
     #[serde(default)]
-    pub self_mute: bool,
-    /// TODO
-    #[serde(default)]
-    pub self_stream: Option<bool>,
-    /// TODO
+    pub volume: Option<Volume>,
+    /// Left/Right balance set by the current user
     #[serde(default)]
-    pub self_video: bool,
-    /// TODO
+    pub pan: Option<Pan>,
+    /// Mute & deafen state of the member
     #[serde(default)]
-    pub suppress: bool,
-    /// TODO
+    pub voice_state: VoiceStateFlags,
+    /// The member
     #[serde(default)]
-    pub request_to_speak_timestamp: Option<DateTime<Local>>,
+    pub user: Option<PartialUser>,
 }
 
 impl VoiceState {
-    /// TODO
-    pub fn user_id(&self) -> Snowflake {
-        self.user_id
-            .or_else(|| self.user.as_ref().map(|u| u.id))
-            .unwrap_or_else(|| self.member.as_ref().unwrap().user.as_ref().unwrap().id)
+    /// ID of the user, `None` if Discord didn't send the user
+    pub fn user_id(&self) -> Option<Snowflake> {
+        self.user.as_ref().map(|u| u.id)
+    }
+
+    /// Name to show for the user: their server nickname, then their display name, then their
+    /// username. `None` if Discord sent neither the nickname nor the user
+    pub fn display_name(&self) -> Option<&str> {
+        self.nick
+            .as_deref()
+            .or_else(|| self.user.as_ref().map(PartialUser::display_name))
     }
 }
 
+/// Mute & deafen state of a member of a voice channel, see [`VoiceState`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoiceStateFlags {
+    /// Muted by the server
+    pub mute: bool,
+    /// Deafened by the server
+    pub deaf: bool,
+    /// Muted by the member
+    pub self_mute: bool,
+    /// Deafened by the member
+    pub self_deaf: bool,
+    /// Not allowed to speak, e.g. in a stage channel
+    pub suppress: bool,
+}
+
+/// Changes to the user's voice settings, applied with
+/// [`Client::set_voice_settings`](crate::Client::set_voice_settings). Unset fields are left
+/// unchanged
+///
+/// Values are clamped to the ranges Discord accepts, and readonly settings such as the available
+/// devices are never sent.
+///
+/// ```no_run
+/// # async fn f(client: &mut discord_ipc::Client<impl discord_ipc::Connection>) -> discord_ipc::Result<()> {
+/// use discord_ipc::voice::VoiceSettingsPatch;
+///
+/// // Sent as an output volume of 200
+/// let patch = VoiceSettingsPatch::new().mute(true).output_volume(250.0);
+/// let settings = client.set_voice_settings(patch).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VoiceSettingsPatch {
+    /// Input device settings
+    #[serde(
+        default,
+        deserialize_with = "input_patch",
+        skip_serializing_if = "DevicePatch::is_empty"
+    )]
+    input: DevicePatch,
+    /// Output device settings
+    #[serde(
+        default,
+        deserialize_with = "output_patch",
+        skip_serializing_if = "DevicePatch::is_empty"
+    )]
+    output: DevicePatch,
+    /// Voice activity or push to talk settings
+    #[serde(
+        default,
+        deserialize_with = "mode_patch",
+        skip_serializing_if = "ModePatch::is_empty"
+    )]
+    mode: ModePatch,
+    /// Automatic gain control
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub automatic_gain_control: Option<bool>,
+    /// Echo cancellation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub echo_cancellation: Option<bool>,
+    /// Noise suppression
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_suppression: Option<bool>,
+    /// Voice quality of service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qos: Option<bool>,
+    /// Warn when the microphone is silent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silence_warning: Option<bool>,
+    /// Deafened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deaf: Option<bool>,
+    /// Muted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<bool>,
+}
+
+/// Device & volume to change
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct DevicePatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume: Option<f32>,
+}
+
+impl DevicePatch {
+    fn is_empty(&self) -> bool {
+        self.device_id.is_none() && self.volume.is_none()
+    }
+}
+
+/// Deserialize input settings, clamped like [`VoiceSettingsPatch::input_volume`]
+fn input_patch<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<DevicePatch, D::Error> {
+    let patch = DevicePatch::deserialize(deserializer)?;
+    Ok(DevicePatch {
+        volume: patch
+            .volume
+            .map(|v| clamp(v, 0.0, VoiceSettingsPatch::MAX_INPUT_VOLUME)),
+        ..patch
+    })
+}
+
+/// Deserialize output settings, clamped like [`VoiceSettingsPatch::output_volume`]
+fn output_patch<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<DevicePatch, D::Error> {
+    let patch = DevicePatch::deserialize(deserializer)?;
+    Ok(DevicePatch {
+        volume: patch
+            .volume
+            .map(|v| clamp(v, 0.0, VoiceSettingsPatch::MAX_OUTPUT_VOLUME)),
+        ..patch
+    })
+}
+
+/// Voice mode settings to change
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ModePatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#type: Option<VoiceMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auto_threshold: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shortcut: Option<Vec<Key>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay: Option<f32>,
+}
+
+impl ModePatch {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Deserialize voice mode settings, clamped like [`VoiceSettingsPatch::vad_threshold`] &
+/// [`VoiceSettingsPatch::ptt_delay`]
+fn mode_patch<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<ModePatch, D::Error> {
+    let patch = ModePatch::deserialize(deserializer)?;
+    Ok(ModePatch {
+        threshold: patch.threshold.map(|t| clamp(t, -100.0, 0.0)),
+        delay: patch
+            .delay
+            .map(|d| clamp(d, 0.0, VoiceSettingsPatch::MAX_PTT_DELAY)),
+        ..patch
+    })
+}
+
+impl VoiceSettingsPatch {
+    /// Loudest input volume Discord accepts
+    pub const MAX_INPUT_VOLUME: f32 = 100.0;
+    /// Loudest output volume Discord accepts
+    pub const MAX_OUTPUT_VOLUME: f32 = 200.0;
+    /// Longest push to talk release delay Discord accepts, in milliseconds
+    pub const MAX_PTT_DELAY: f32 = 2000.0;
+
+    /// Create a patch changing nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the input device with ID `device_id`
+    pub fn input_device(mut self, device_id: impl Into<String>) -> Self {
+        self.input.device_id = Some(device_id.into());
+        self
+    }
+
+    /// Set the input volume, clamped to 0 to [`MAX_INPUT_VOLUME`](Self::MAX_INPUT_VOLUME)
+    pub fn input_volume(mut self, volume: f32) -> Self {
+        self.input.volume = Some(clamp(volume, 0.0, Self::MAX_INPUT_VOLUME));
+        self
+    }
+
+    /// Use the output device with ID `device_id`
+    pub fn output_device(mut self, device_id: impl Into<String>) -> Self {
+        self.output.device_id = Some(device_id.into());
+        self
+    }
+
+    /// Set the output volume, clamped to 0 to [`MAX_OUTPUT_VOLUME`](Self::MAX_OUTPUT_VOLUME)
+    pub fn output_volume(mut self, volume: f32) -> Self {
+        self.output.volume = Some(clamp(volume, 0.0, Self::MAX_OUTPUT_VOLUME));
+        self
+    }
+
+    /// Switch between push to talk & voice activity
+    pub fn voice_mode(mut self, mode: VoiceMode) -> Self {
+        self.mode.r#type = Some(mode);
+        self
+    }
+
+    /// Set the voice activity threshold, clamped to -100 to 0 dB
+    pub fn vad_threshold(mut self, threshold: f32) -> Self {
+        self.mode.threshold = Some(clamp(threshold, -100.0, 0.0));
+        self
+    }
+
+    /// Set whether the voice activity threshold is automatic
+    pub fn auto_threshold(mut self, auto: bool) -> Self {
+        self.mode.auto_threshold = Some(auto);
+        self
+    }
+
+    /// Set the push to talk shortcut
+    pub fn ptt_shortcut(mut self, shortcut: Vec<Key>) -> Self {
+        self.mode.shortcut = Some(shortcut);
+        self
+    }
+
+    /// Set the push to talk release delay in milliseconds, clamped to 0 to
+    /// [`MAX_PTT_DELAY`](Self::MAX_PTT_DELAY)
+    pub fn ptt_delay(mut self, delay: f32) -> Self {
+        self.mode.delay = Some(clamp(delay, 0.0, Self::MAX_PTT_DELAY));
+        self
+    }
+
+    /// Set automatic gain control
+    pub fn automatic_gain_control(mut self, enabled: bool) -> Self {
+        self.automatic_gain_control = Some(enabled);
+        self
+    }
+
+    /// Set echo cancellation
+    pub fn echo_cancellation(mut self, enabled: bool) -> Self {
+        self.echo_cancellation = Some(enabled);
+        self
+    }
+
+    /// Set noise suppression
+    pub fn noise_suppression(mut self, enabled: bool) -> Self {
+        self.noise_suppression = Some(enabled);
+        self
+    }
+
+    /// Set voice quality of service
+    pub fn qos(mut self, enabled: bool) -> Self {
+        self.qos = Some(enabled);
+        self
+    }
+
+    /// Set whether to warn when the microphone is silent
+    pub fn silence_warning(mut self, enabled: bool) -> Self {
+        self.silence_warning = Some(enabled);
+        self
+    }
+
+    /// Deafen or undeafen
+    pub fn deaf(mut self, deaf: bool) -> Self {
+        self.deaf = Some(deaf);
+        self
+    }
+
+    /// Mute or unmute
+    pub fn mute(mut self, mute: bool) -> Self {
+        self.mute = Some(mute);
+        self
+    }
+}
+
+impl From<SetVoiceSettings> for VoiceSettingsPatch {
+    fn from(settings: SetVoiceSettings) -> Self {
+        let mut patch = Self {
+            automatic_gain_control: settings.automatic_gain_control,
+            echo_cancellation: settings.echo_cancellation,
+            noise_suppression: settings.noise_suppression,
+            qos: settings.qos,
+            silence_warning: settings.silence_warning,
+            deaf: settings.deaf,
+            mute: settings.mute,
+            ..Self::default()
+        };
+        if let Some(input) = settings.input {
+            patch = patch
+                .input_device(input.device_id)
+                .input_volume(input.volume);
+        }
+        if let Some(output) = settings.output {
+            patch = patch
+                .output_device(output.device_id)
+                .output_volume(output.volume);
+        }
+        if let Some(mode) = settings.mode {
+            patch.mode = ModePatch {
+                r#type: Some(mode.r#type),
+                auto_threshold: mode.auto_threshold,
+                shortcut: Some(mode.shortcut),
+                ..ModePatch::default()
+            };
+            patch = patch.vad_threshold(mode.threshold).ptt_delay(mode.delay);
+        }
+        patch
+    }
+}
+
+/// Options for [`Client::join_voice`](crate::Client::join_voice)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct JoinOptions {
+    /// How long Discord may take to join, rounded up to whole seconds. Fails with
+    /// [`Error::SelectChannelTimedOut`](crate::Error::SelectChannelTimedOut) once elapsed
+    pub timeout: Option<Duration>,
+    /// Move the user even if they're already in a voice channel
+    pub force: bool,
+}
+
+impl JoinOptions {
+    /// Timeout in the whole seconds Discord expects. Rounded up, since a timeout of 0 fails
+    /// immediately
+    pub(crate) fn timeout_secs(&self) -> Option<u64> {
+        self.timeout
+            .map(|t| t.as_secs() + u64::from(t.subsec_nanos() > 0))
+    }
+
+    /// Command joining `channel_id`
+    pub(crate) fn command(&self, channel_id: Snowflake) -> Command {
+        Command::SelectVoiceChannel {
+            channel_id: Some(channel_id),
+            timeout: self.timeout_secs(),
+            force: self.force.then_some(true),
+        }
+    }
+}
+
+/// Changes to the voice settings of another user, applied with
+/// [`Client::set_user_voice_settings`](crate::Client::set_user_voice_settings). Unset fields are left unchanged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserVoicePatch {
+    /// Left/Right balance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pan: Option<Pan>,
+    /// User volume
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume: Option<Volume>,
+    /// Muted for the current user only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mute: Option<bool>,
+}
+
+impl UserVoicePatch {
+    /// Create a patch changing nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the left/right balance
+    pub fn pan(mut self, pan: Pan) -> Self {
+        self.pan = Some(pan);
+        self
+    }
+
+    /// Set the volume
+    pub fn volume(mut self, volume: Volume) -> Self {
+        self.volume = Some(volume);
+        self
+    }
+
+    /// Mute or unmute the user for the current user only
+    pub fn mute(mut self, mute: bool) -> Self {
+        self.mute = Some(mute);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn voice_settings_patches() {
+        let patch = VoiceSettingsPatch::new()
+            .mute(true)
+            .input_volume(-5.0)
+            .output_volume(250.0)
+            .output_device("speakers")
+            .ptt_delay(f32::NAN);
+        assert_eq!(
+            serde_json::to_value(&patch).unwrap(),
+            json!({
+                "input": { "volume": 0.0 },
+                "output": { "device_id": "speakers", "volume": 200.0 },
+                "mode": { "delay": 0.0 },
+                "mute": true
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(VoiceSettingsPatch::new()).unwrap(),
+            json!({})
+        );
+
+        // Readonly devices aren't sent
+        let patch = VoiceSettingsPatch::from(SetVoiceSettings {
+            input: Some(InputSettings {
+                device_id: "mic".into(),
+                volume: 150.0,
+                available_devices: vec![Device {
+                    id: "mic".into(),
+                    name: "Microphone".into(),
+                }],
+            }),
+            ..Default::default()
+        });
+        assert_eq!(
+            serde_json::to_value(&patch).unwrap(),
+            json!({ "input": { "device_id": "mic", "volume": 100.0 } })
+        );
+
+        assert_eq!(Volume::new(250).get(), Volume::MAX);
+        let pan = Pan::new(1.5, -1.0);
+        assert_eq!((pan.left(), pan.right()), (1.0, 0.0));
+
+        // Deserialized values are clamped too
+        let volume: Volume = serde_json::from_value(json!(250)).unwrap();
+        assert_eq!(volume.get(), Volume::MAX);
+        let pan: Pan = serde_json::from_value(json!({ "left": 5.0, "right": 0.5 })).unwrap();
+        assert_eq!((pan.left(), pan.right()), (1.0, 0.5));
+        let patch: UserVoicePatch = serde_json::from_value(json!({ "volume": 250 })).unwrap();
+        assert_eq!(patch, UserVoicePatch::new().volume(Volume::new(200)));
+        let patch: VoiceSettingsPatch = serde_json::from_value(json!({
+            "input": { "volume": 150.0 },
+            "output": { "device_id": "speakers", "volume": -5.0 },
+            "mode": { "threshold": 10.0, "delay": 5000.0 },
+            "deaf": true
+        }))
+        .unwrap();
+        assert_eq!(
+            patch,
+            VoiceSettingsPatch::new()
+                .input_volume(100.0)
+                .output_device("speakers")
+                .output_volume(0.0)
+                .vad_threshold(0.0)
+                .ptt_delay(VoiceSettingsPatch::MAX_PTT_DELAY)
+                .deaf(true)
+        );
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    mod client {
+        use serde_json::{json, Value};
+
+        use super::*;
+        use crate::{
+            codec::OpCode,
+            mock::{channel, client, voice_state, MockDiscord},
+            Error,
+        };
+
+        #[tokio::test]
+        async fn user_voice_settings() {
+            let (socket, discord) = MockDiscord::spawn(|mut discord| {
+                discord.handshake();
+                let payload = discord.expect_command("SET_USER_VOICE_SETTINGS");
+                assert_eq!(
+                    payload["args"],
+                    json!({ "user_id": "8", "pan": { "left": 1.0, "right": 0.5 }, "mute": true })
+                );
+                discord.respond("SET_USER_VOICE_SETTINGS", payload["args"].clone());
+
+                discord.expect_command("GET_SELECTED_VOICE_CHANNEL");
+                discord.respond(
+                    "GET_SELECTED_VOICE_CHANNEL",
+                    channel(
+                        "10",
+                        json!([
+                            voice_state("7", false),
+                            voice_state("8", false),
+                            voice_state("9", true)
+                        ]),
+                    ),
+                );
+                for user_id in ["8", "9"] {
+                    let payload = discord.expect_command("SET_USER_VOICE_SETTINGS");
+                    assert_eq!(
+                        payload["args"],
+                        json!({ "user_id": user_id, "volume": 200 })
+                    );
+                    discord.respond("SET_USER_VOICE_SETTINGS", payload["args"].clone());
+                }
+            });
+            let mut client = client(socket).await;
+
+            let patch = UserVoicePatch::new().pan(Pan::new(2.0, 0.5)).mute(true);
+            let settings = client
+                .set_user_voice_settings(Snowflake(8), patch)
+                .await
+                .unwrap();
+            assert_eq!(settings.mute, Some(true));
+
+            // The current user is skipped
+            let updated = client.set_channel_volume(Volume::new(250)).await.unwrap();
+            assert_eq!(
+                updated.iter().map(|s| s.user_id).collect::<Vec<_>>(),
+                [Snowflake(8), Snowflake(9)]
+            );
+            assert!(updated.iter().all(|s| s.volume == Some(Volume::new(200))));
+            discord.join().unwrap();
+        }
+
+        #[tokio::test]
+        async fn select_channels() {
+            let (socket, discord) = MockDiscord::spawn(|mut discord| {
+                discord.handshake();
+                let payload = discord.expect_command("SELECT_VOICE_CHANNEL");
+                assert_eq!(payload["args"], json!({ "channel_id": "10", "timeout": 5 }));
+                discord.write_frame(
+                    OpCode::FRAME,
+                    json!({
+                        "cmd": "SELECT_VOICE_CHANNEL",
+                        "evt": "ERROR",
+                        "data": { "code": 5003, "message": "Force required" },
+                        "nonce": payload["nonce"]
+                    }),
+                );
+                let payload = discord.expect_command("SELECT_VOICE_CHANNEL");
+                assert_eq!(
+                    payload["args"],
+                    json!({ "channel_id": "10", "force": true })
+                );
+                discord.respond("SELECT_VOICE_CHANNEL", channel("10", json!([])));
+
+                let payload = discord.expect_command("SELECT_VOICE_CHANNEL");
+                assert_eq!(payload["args"], json!({ "channel_id": null }));
+                discord.respond("SELECT_VOICE_CHANNEL", Value::Null);
+
+                let payload = discord.expect_command("SELECT_TEXT_CHANNEL");
+                assert_eq!(payload["args"], json!({ "channel_id": "11" }));
+                discord.write_frame(
+                    OpCode::FRAME,
+                    json!({
+                        "cmd": "SELECT_TEXT_CHANNEL",
+                        "evt": "ERROR",
+                        "data": { "code": 5001, "message": "Request to select channel timed out" },
+                        "nonce": payload["nonce"]
+                    }),
+                );
+            });
+            let mut client = client(socket).await;
+
+            // Discord only takes whole seconds
+            let options = JoinOptions {
+                timeout: Some(Duration::from_millis(4500)),
+                force: false,
+            };
+            let err = client.join_voice(Snowflake(10), options).await.unwrap_err();
+            assert!(matches!(err, Error::ForceRequired));
+            assert_eq!(
+                err.rpc_code(),
+                Some(crate::command::RpcErrorCode::SelectVoiceForceRequired)
+            );
+            let options = JoinOptions {
+                force: true,
+                ..Default::default()
+            };
+            let channel = client.join_voice(Snowflake(10), options).await.unwrap();
+            assert_eq!(channel.id, Snowflake(10));
+            client.leave_voice().await.unwrap();
+            assert!(matches!(
+                client.focus_text_channel(Snowflake(11)).await,
+                Err(Error::SelectChannelTimedOut)
+            ));
+            discord.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! Mute, deafen, input mode and shortcut controls
+
+use serde::de::IgnoredAny;
+
+use super::{Key, VoiceMode, VoiceSettingsPatch};
+use crate::{
+    command::{CaptureShortcutAction, Command, EventResponse, GetVoiceSettings},
+    ipc::RawEvent,
+    Client, Connection, EventSubscribe, Result,
+};
+
+/// Voice input mode, with its settings
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoiceInputMode {
+    /// Transmit while the shortcut is held
+    PushToTalk {
+        /// Shortcut keys
+        keys: Vec<Key>,
+        /// Milliseconds to keep transmitting after the shortcut is released
+        delay: f32,
+    },
+    /// Transmit when the microphone picks up voice
+    VoiceActivity,
+}
+
+/// Mute, deafen & voice mode controls for hotkey tools
+///
+/// Toggles need the current state. [`start`](Self::start) subscribes to voice settings updates
+/// and loads the settings, which are then kept up to date from the responses to changes and from
+/// the events passed to [`handle`](Self::handle), so that a toggle flips the state Discord
+/// actually has rather than a stale one. Without `start`, the settings are fetched before each
+/// change. Requires the `rpc`, `rpc.voice.read` & `rpc.voice.write` scopes.
+#[derive(Debug, Clone, Default)]
+pub struct VoiceControl {
+    settings: Option<GetVoiceSettings>,
+    subscribed: bool,
+}
+
+impl VoiceControl {
+    /// Create a control that fetches the settings when needed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to voice settings updates and load the current settings
+    pub async fn start<C: Connection>(&mut self, client: &mut Client<C>) -> Result<()> {
+        client
+            .subscribe(EventSubscribe::VoiceSettingsUpdate)
+            .await?;
+        self.subscribed = true;
+        self.settings = Some(client.get_voice_settings().await?);
+        Ok(())
+    }
+
+    /// The last known settings
+    pub fn settings(&self) -> Option<&GetVoiceSettings> {
+        self.settings.as_ref()
+    }
+
+    /// Update the settings from an event. Other events are ignored
+    pub fn handle(&mut self, event: &EventResponse) {
+        if let (EventResponse::VoiceSettingsUpdate(update), Some(settings)) =
+            (event, &mut self.settings)
+        {
+            settings.apply(update);
+        }
+    }
+
+    /// Current settings, fetched unless kept up to date by events
+    async fn current<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+    ) -> Result<&GetVoiceSettings> {
+        if !self.subscribed || self.settings.is_none() {
+            self.settings = Some(client.get_voice_settings().await?);
+        }
+        Ok(self.settings.as_ref().expect("Settings were just loaded"))
+    }
+
+    async fn set<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+        patch: VoiceSettingsPatch,
+    ) -> Result<&GetVoiceSettings> {
+        let settings = client.set_voice_settings(patch).await?;
+        Ok(self.settings.insert(settings))
+    }
+
+    /// Mute if unmuted and unmute if muted, returning whether the user is now muted
+    pub async fn toggle_mute<C: Connection>(&mut self, client: &mut Client<C>) -> Result<bool> {
+        let mute = !self.current(client).await?.mute.unwrap_or(false);
+        let settings = self
+            .set(client, VoiceSettingsPatch::new().mute(mute))
+            .await?;
+        Ok(settings.mute.unwrap_or(mute))
+    }
+
+    /// Deafen if undeafened and undeafen if deafened, returning whether the user is now deafened
+    pub async fn toggle_deaf<C: Connection>(&mut self, client: &mut Client<C>) -> Result<bool> {
+        let deaf = !self.current(client).await?.deaf.unwrap_or(false);
+        let settings = self
+            .set(client, VoiceSettingsPatch::new().deaf(deaf))
+            .await?;
+        Ok(settings.deaf.unwrap_or(deaf))
+    }
+
+    /// Switch to push to talk or voice activity
+    pub async fn set_voice_mode<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+        mode: VoiceInputMode,
+    ) -> Result<()> {
+        let patch = match mode {
+            VoiceInputMode::PushToTalk { keys, delay } => VoiceSettingsPatch::new()
+                .voice_mode(VoiceMode::PushToTalk)
+                .ptt_shortcut(keys)
+                .ptt_delay(delay),
+            VoiceInputMode::VoiceActivity => {
+                VoiceSettingsPatch::new().voice_mode(VoiceMode::VoiceActivity)
+            }
+        };
+        self.set(client, patch).await.map(|_| ())
+    }
+
+    /// Set the voice activity threshold in dB, clamped to -100 to 0, and whether Discord adjusts
+    /// it automatically
+    pub async fn set_vad_threshold<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+        db: f32,
+        auto: bool,
+    ) -> Result<()> {
+        let patch = VoiceSettingsPatch::new()
+            .vad_threshold(db)
+            .auto_threshold(auto);
+        self.set(client, patch).await.map(|_| ())
+    }
+}
+
+/// Shortcut being recorded, see [`Client::capture_shortcut`]
+///
+/// Each key combination the user presses is returned by [`next`](Self::next) until
+/// [`stop`](Self::stop) is called. Other events received meanwhile are kept and returned by
+/// [`Client::event`] once the capture is stopped or dropped.
+pub struct ShortcutCapture<'a, C> {
+    client: &'a mut Client<C>,
+    skipped: Vec<RawEvent>,
+}
+
+impl<'a, C: Connection> ShortcutCapture<'a, C> {
+    pub(crate) fn new(client: &'a mut Client<C>) -> Self {
+        Self {
+            client,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Wait for the user to press a key combination
+    pub async fn next(&mut self) -> Result<Vec<Key>> {
+        loop {
+            match self.client.framed.event().await? {
+                (EventResponse::CaptureShortcutChange(change), _) => return Ok(change.shortcut),
+                e => self.skipped.push(e),
+            }
+        }
+    }
+
+    /// Stop recording, returning the skipped events to the client
+    pub async fn stop(self) -> Result<()> {
+        // The skipped events are requeued when `self` is dropped
+        self.client
+            .framed
+            .command::<IgnoredAny>(Command::CaptureShortcut {
+                action: CaptureShortcutAction::Stop,
+            })
+            .await
+            .map(|_| ())
+    }
+}
+
+impl<C> Drop for ShortcutCapture<'_, C> {
+    fn drop(&mut self) {
+        self.client
+            .framed
+            .requeue(std::mem::take(&mut self.skipped));
+    }
+}
+
+#[cfg(all(test, unix, feature = "tokio"))]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        command::VoiceSettingsUpdate,
+        mock::{client, MockDiscord},
+        voice::KeyType,
+    };
+
+    #[tokio::test]
+    async fn voice_controls() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            let payload = discord.expect_command("SUBSCRIBE");
+            assert_eq!(payload["evt"], "VOICE_SETTINGS_UPDATE");
+            discord.respond("SUBSCRIBE", json!({ "evt": "VOICE_SETTINGS_UPDATE" }));
+            discord.expect_command("GET_VOICE_SETTINGS");
+            discord.respond(
+                "GET_VOICE_SETTINGS",
+                json!({ "mute": false, "deaf": false }),
+            );
+
+            let payload = discord.expect_command("SET_VOICE_SETTINGS");
+            assert_eq!(payload["args"], json!({ "mute": true }));
+            discord.respond("SET_VOICE_SETTINGS", json!({ "mute": true, "deaf": false }));
+            // Unmuted in Discord
+            discord.dispatch("VOICE_SETTINGS_UPDATE", json!({ "mute": false }));
+            let payload = discord.expect_command("SET_VOICE_SETTINGS");
+            assert_eq!(payload["args"], json!({ "mute": true }));
+            discord.respond("SET_VOICE_SETTINGS", json!({ "mute": true, "deaf": false }));
+
+            let payload = discord.expect_command("SET_VOICE_SETTINGS");
+            assert_eq!(
+                payload["args"],
+                json!({
+                    "mode": {
+                        "type": "PUSH_TO_TALK",
+                        "shortcut": [{ "type": 0, "code": 80, "name": "P" }],
+                        "delay": 20.0
+                    }
+                })
+            );
+            discord.respond("SET_VOICE_SETTINGS", json!({ "mute": true, "deaf": false }));
+        });
+        let mut client = client(socket).await;
+
+        let mut control = VoiceControl::new();
+        control.start(&mut client).await.unwrap();
+        assert!(control.toggle_mute(&mut client).await.unwrap());
+        control.handle(&client.event().await.unwrap());
+        assert_eq!(control.settings().unwrap().mute, Some(false));
+        assert!(control.toggle_mute(&mut client).await.unwrap());
+        control
+            .set_voice_mode(
+                &mut client,
+                VoiceInputMode::PushToTalk {
+                    keys: vec![Key {
+                        r#type: KeyType::KeyboardKey,
+                        code: 80,
+                        name: "P".into(),
+                    }],
+                    delay: 20.0,
+                },
+            )
+            .await
+            .unwrap();
+        discord.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn shortcut_capture() {
+        let (socket, discord) = MockDiscord::spawn(|mut discord| {
+            discord.handshake();
+            let payload = discord.expect_command("CAPTURE_SHORTCUT");
+            assert_eq!(payload["args"], json!({ "action": "START" }));
+            discord.respond("CAPTURE_SHORTCUT", Value::Null);
+            discord.dispatch("VOICE_SETTINGS_UPDATE", json!({ "mute": true }));
+            discord.dispatch(
+                "CAPTURE_SHORTCUT_CHANGE",
+                json!({ "shortcut": [
+                    { "type": 2, "code": 162, "name": "ctrl" },
+                    { "type": 0, "code": 80, "name": "P" }
+                ] }),
+            );
+            let payload = discord.expect_command("CAPTURE_SHORTCUT");
+            assert_eq!(payload["args"], json!({ "action": "STOP" }));
+            discord.respond("CAPTURE_SHORTCUT", Value::Null);
+
+            discord.expect_command("CAPTURE_SHORTCUT");
+            discord.respond("CAPTURE_SHORTCUT", Value::Null);
+            discord.dispatch("VOICE_SETTINGS_UPDATE", json!({ "mute": false }));
+            discord.dispatch(
+                "CAPTURE_SHORTCUT_CHANGE",
+                json!({ "shortcut": [{ "type": 0, "code": 80, "name": "P" }] }),
+            );
+        });
+        let mut client = client(socket).await;
+
+        let mut capture = client.capture_shortcut().await.unwrap();
+        let keys = capture.next().await.unwrap();
+        assert_eq!(
+            keys.iter()
+                .map(|k| (k.r#type, k.name.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (KeyType::KeyboardModifierKey, "ctrl"),
+                (KeyType::KeyboardKey, "P")
+            ]
+        );
+        capture.stop().await.unwrap();
+        // Skipped while capturing
+        assert!(matches!(
+            client.event().await.unwrap(),
+            EventResponse::VoiceSettingsUpdate(VoiceSettingsUpdate {
+                mute: Some(true),
+                ..
+            })
+        ));
+
+        // Dropping the capture also returns the skipped events
+        let mut capture = client.capture_shortcut().await.unwrap();
+        assert_eq!(capture.next().await.unwrap().len(), 1);
+        drop(capture);
+        assert!(matches!(
+            client.event().await.unwrap(),
+            EventResponse::VoiceSettingsUpdate(VoiceSettingsUpdate {
+                mute: Some(false),
+                ..
+            })
+        ));
+        discord.join().unwrap();
+    }
+}
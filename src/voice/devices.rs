@@ -0,0 +1,326 @@
+//! Picking audio devices and watching them change
+
+use super::Device;
+use crate::{
+    command::{GetVoiceSettings, VoiceSettingsUpdate},
+    Error, Result,
+};
+
+/// Input or output device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    /// Microphone
+    Input,
+    /// Speakers or headphones
+    Output,
+}
+
+/// How to pick an audio device from the available devices
+///
+/// Strings convert to [`DeviceMatcher::Any`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceMatcher {
+    /// Device with this exact ID
+    Id(String),
+    /// Device with this name, ignoring case
+    Name(String),
+    /// The only device whose name contains this, ignoring case
+    Substring(String),
+    /// Try the ID, then the name, then a substring of the name
+    Any(String),
+}
+
+impl DeviceMatcher {
+    /// Find the matching device
+    pub fn find<'a>(&self, devices: &'a [Device]) -> Result<&'a Device> {
+        let found = match self {
+            Self::Id(id) => devices.iter().find(|d| &d.id == id),
+            Self::Name(name) => {
+                let name = name.to_lowercase();
+                devices.iter().find(|d| d.name.to_lowercase() == name)
+            }
+            Self::Substring(part) => {
+                let part = part.to_lowercase();
+                let matches: Vec<_> = devices
+                    .iter()
+                    .filter(|d| d.name.to_lowercase().contains(&part))
+                    .collect();
+                if matches.len() > 1 {
+                    return Err(Error::AmbiguousDevice {
+                        matcher: self.to_string(),
+                        candidates: matches.iter().map(|d| d.name.clone()).collect(),
+                    });
+                }
+                matches.first().copied()
+            }
+            Self::Any(s) => {
+                return Self::Id(s.clone())
+                    .find(devices)
+                    .or_else(|_| Self::Name(s.clone()).find(devices))
+                    .or_else(|_| Self::Substring(s.clone()).find(devices));
+            }
+        };
+        found.ok_or_else(|| Error::DeviceNotFound(self.to_string()))
+    }
+}
+
+impl std::fmt::Display for DeviceMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "ID {id:?}"),
+            Self::Name(name) => write!(f, "name {name:?}"),
+            Self::Substring(part) => write!(f, "name containing {part:?}"),
+            Self::Any(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+
+impl From<&str> for DeviceMatcher {
+    fn from(s: &str) -> Self {
+        Self::Any(s.to_string())
+    }
+}
+
+impl From<String> for DeviceMatcher {
+    fn from(s: String) -> Self {
+        Self::Any(s)
+    }
+}
+
+/// Change to the available or selected audio devices, see [`DeviceWatcher`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceChange {
+    /// A device was plugged in
+    Added(DeviceKind, Device),
+    /// A device was unplugged
+    Removed(DeviceKind, Device),
+    /// A different device was selected, by ID
+    Selected(DeviceKind, String),
+}
+
+/// Tracks audio devices from `VOICE_SETTINGS_UPDATE` events, reporting devices being plugged in,
+/// unplugged or selected
+///
+/// Subscribe to [`EventSubscribe::VoiceSettingsUpdate`](crate::EventSubscribe::VoiceSettingsUpdate), [`seed`](Self::seed) the watcher with
+/// the current settings, then pass each [`VoiceSettingsUpdate`] to [`update`](Self::update).
+#[derive(Debug, Clone, Default)]
+pub struct DeviceWatcher {
+    input: Option<(String, Vec<Device>)>,
+    output: Option<(String, Vec<Device>)>,
+}
+
+impl DeviceWatcher {
+    /// Create a watcher that reports nothing until it's seeded or updated once
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the devices to compare the next update to, without reporting changes
+    pub fn seed(&mut self, settings: &GetVoiceSettings) {
+        if let Some(input) = &settings.input {
+            self.input = Some((input.device_id.clone(), input.available_devices.clone()));
+        }
+        if let Some(output) = &settings.output {
+            self.output = Some((output.device_id.clone(), output.available_devices.clone()));
+        }
+    }
+
+    /// Compare an update to the previous devices
+    pub fn update(&mut self, update: &VoiceSettingsUpdate) -> Vec<DeviceChange> {
+        let mut changes = vec![];
+        if let Some(input) = &update.input {
+            changes.extend(diff(
+                DeviceKind::Input,
+                &mut self.input,
+                &input.device_id,
+                input.available_devices.as_deref(),
+            ));
+        }
+        if let Some(output) = &update.output {
+            changes.extend(diff(
+                DeviceKind::Output,
+                &mut self.output,
+                &output.device_id,
+                output.available_devices.as_deref(),
+            ));
+        }
+        changes
+    }
+
+    /// Available devices of a kind, as last seen
+    pub fn devices(&self, kind: DeviceKind) -> &[Device] {
+        let devices = match kind {
+            DeviceKind::Input => &self.input,
+            DeviceKind::Output => &self.output,
+        };
+        devices.as_ref().map_or(&[], |(_, devices)| devices)
+    }
+}
+
+/// Changes between the `previous` devices and the current ones, replacing `previous`
+fn diff(
+    kind: DeviceKind,
+    previous: &mut Option<(String, Vec<Device>)>,
+    selected: &str,
+    devices: Option<&[Device]>,
+) -> Vec<DeviceChange> {
+    let mut changes = vec![];
+    if let Some((previous_selected, previous_devices)) = previous {
+        // Updates without the device list only change the selection
+        if let Some(devices) = devices {
+            for device in previous_devices.iter() {
+                if !devices.iter().any(|d| d.id == device.id) {
+                    changes.push(DeviceChange::Removed(kind, device.clone()));
+                }
+            }
+            for device in devices {
+                if !previous_devices.iter().any(|d| d.id == device.id) {
+                    changes.push(DeviceChange::Added(kind, device.clone()));
+                }
+            }
+            *previous_devices = devices.to_vec();
+        }
+        if previous_selected != selected {
+            changes.push(DeviceChange::Selected(kind, selected.to_string()));
+            *previous_selected = selected.to_string();
+        }
+    } else {
+        *previous = Some((selected.to_string(), devices.unwrap_or_default().to_vec()));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voice::InputSettingsUpdate;
+
+    fn device(id: &str, name: &str) -> Device {
+        Device {
+            id: id.into(),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn device_matchers() {
+        let devices = [
+            device("default", "Default"),
+            device("usb-1", "USB Headset Microphone"),
+            device("usb-2", "USB Webcam Microphone"),
+        ];
+        let find = |m: &str| DeviceMatcher::from(m).find(&devices).map(|d| d.id.as_str());
+        assert_eq!(find("usb-2").unwrap(), "usb-2");
+        assert_eq!(find("usb webcam microphone").unwrap(), "usb-2");
+        assert_eq!(find("headset").unwrap(), "usb-1");
+        assert!(matches!(
+            find("microphone"),
+            Err(Error::AmbiguousDevice { candidates, .. }) if candidates.len() == 2
+        ));
+        assert!(matches!(find("speakers"), Err(Error::DeviceNotFound(_))));
+        assert!(DeviceMatcher::Id("Default".into()).find(&devices).is_err());
+        // Names and substrings are compared the same way, beyond ASCII
+        let devices = [device("bt-1", "Écouteurs")];
+        assert!(DeviceMatcher::Name("écouteurs".into())
+            .find(&devices)
+            .is_ok());
+        assert!(DeviceMatcher::Substring("écout".into())
+            .find(&devices)
+            .is_ok());
+    }
+
+    #[test]
+    fn device_changes() {
+        let update = |selected: &str, devices: Option<Vec<Device>>| VoiceSettingsUpdate {
+            input: Some(InputSettingsUpdate {
+                device_id: selected.into(),
+                volume: 100.0,
+                available_devices: devices,
+            }),
+            output: None,
+            mode: None,
+            automatic_gain_control: None,
+            echo_cancellation: None,
+            noise_suppression: None,
+            qos: None,
+            silence_warning: None,
+            deaf: None,
+            mute: None,
+        };
+        let mut watcher = DeviceWatcher::new();
+        let headset = device("usb-1", "USB Headset");
+        assert!(watcher
+            .update(&update("default", Some(vec![device("default", "Default")])))
+            .is_empty());
+        assert_eq!(
+            watcher.update(&update(
+                "usb-1",
+                Some(vec![device("default", "Default"), headset.clone()])
+            )),
+            [
+                DeviceChange::Added(DeviceKind::Input, headset.clone()),
+                DeviceChange::Selected(DeviceKind::Input, "usb-1".into()),
+            ]
+        );
+        assert_eq!(watcher.devices(DeviceKind::Input).len(), 2);
+        assert!(watcher.update(&update("usb-1", None)).is_empty());
+        assert_eq!(
+            watcher.update(&update("default", Some(vec![device("default", "Default")]))),
+            [
+                DeviceChange::Removed(DeviceKind::Input, headset),
+                DeviceChange::Selected(DeviceKind::Input, "default".into()),
+            ]
+        );
+        // Unplugging the last device leaves an empty list
+        assert_eq!(
+            watcher.update(&update("default", Some(vec![]))),
+            [DeviceChange::Removed(
+                DeviceKind::Input,
+                device("default", "Default")
+            )]
+        );
+        assert!(watcher.devices(DeviceKind::Output).is_empty());
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    mod client {
+        use serde_json::json;
+
+        use super::*;
+        use crate::mock::{client, MockDiscord};
+
+        #[tokio::test]
+        async fn selects_devices() {
+            let (socket, discord) = MockDiscord::spawn(|mut discord| {
+                discord.handshake();
+                let settings = json!({
+                    "input": {
+                        "device_id": "default",
+                        "volume": 100.0,
+                        "available_devices": [
+                            { "id": "default", "name": "Default" },
+                            { "id": "usb-1", "name": "USB Headset" }
+                        ]
+                    }
+                });
+                discord.expect_command("GET_VOICE_SETTINGS");
+                discord.respond("GET_VOICE_SETTINGS", settings.clone());
+                let payload = discord.expect_command("SET_VOICE_SETTINGS");
+                assert_eq!(
+                    payload["args"],
+                    json!({ "input": { "device_id": "usb-1" } })
+                );
+                discord.respond("SET_VOICE_SETTINGS", settings.clone());
+                discord.expect_command("GET_VOICE_SETTINGS");
+                discord.respond("GET_VOICE_SETTINGS", settings);
+            });
+            let mut client = client(socket).await;
+            client.select_input_device("headset").await.unwrap();
+            assert!(matches!(
+                client.select_input_device("speakers").await,
+                Err(Error::DeviceNotFound(_))
+            ));
+            discord.join().unwrap();
+        }
+    }
+}
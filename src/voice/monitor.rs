@@ -0,0 +1,319 @@
+//! Voice connection quality
+
+use std::collections::VecDeque;
+
+use log::*;
+
+use super::{Ping, VoiceConnectionState};
+use crate::{command::EventResponse, Client, Connection, EventSubscribe, Result};
+
+/// Ping statistics over the recent samples, see [`ConnectionMonitor`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingStats {
+    /// Latest ping, in milliseconds
+    pub last: u64,
+    /// Rolling average, in milliseconds
+    pub average: f64,
+    /// Average difference between consecutive pings, in milliseconds
+    pub jitter: f64,
+    /// Median ping, in milliseconds
+    pub median: u64,
+    /// 95th percentile ping, in milliseconds
+    pub p95: u64,
+    /// Highest ping, in milliseconds
+    pub max: u64,
+    /// Number of pings the statistics cover
+    pub samples: usize,
+}
+
+/// Change worth warning about, see [`ConnectionMonitor`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionAlert {
+    /// The connection state changed
+    State(VoiceConnectionState),
+    /// The average ping rose above the threshold
+    HighPing(PingStats),
+    /// The jitter rose above the threshold
+    HighJitter(PingStats),
+    /// Ping & jitter are back below their thresholds
+    Recovered(PingStats),
+}
+
+/// Voice connection quality monitor
+///
+/// Keeps the latest pings from [`VoiceConnectionStatus`](crate::command::VoiceConnectionStatus)
+/// events while voice is connected, and raises an alert once when the average ping or jitter
+/// crosses its threshold, and again when both have recovered. Samples are cleared when the
+/// voice server changes. A status repeating the previous status's `pings`, e.g. one only
+/// reporting a state change, doesn't add a sample.
+///
+/// ```no_run
+/// # async fn f(client: &mut discord_ipc::Client<impl discord_ipc::Connection>) -> discord_ipc::Result<()> {
+/// use discord_ipc::voice::ConnectionMonitor;
+///
+/// let mut monitor = ConnectionMonitor::new().ping_threshold(150).jitter_threshold(30);
+/// monitor.start(client).await?;
+/// loop {
+///     for alert in monitor.handle(&client.event().await?) {
+///         println!("{alert:?}");
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionMonitor {
+    state: Option<VoiceConnectionState>,
+    hostname: Option<String>,
+    samples: VecDeque<u64>,
+    /// `pings` of the last status, to recognize statuses without a new ping
+    pings: Vec<Ping>,
+    window: usize,
+    ping_threshold: Option<u64>,
+    jitter_threshold: Option<u64>,
+    high_ping: bool,
+    high_jitter: bool,
+}
+
+impl Default for ConnectionMonitor {
+    fn default() -> Self {
+        Self {
+            state: None,
+            hostname: None,
+            samples: VecDeque::new(),
+            pings: Vec::new(),
+            window: Self::DEFAULT_WINDOW,
+            ping_threshold: None,
+            jitter_threshold: None,
+            high_ping: false,
+            high_jitter: false,
+        }
+    }
+}
+
+impl ConnectionMonitor {
+    /// Number of pings kept by default
+    pub const DEFAULT_WINDOW: usize = 20;
+
+    /// Create a monitor without thresholds
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of pings the statistics cover, at least 1
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Alert when the average ping rises above `ms` milliseconds
+    pub fn ping_threshold(mut self, ms: u64) -> Self {
+        self.ping_threshold = Some(ms);
+        self
+    }
+
+    /// Alert when the jitter rises above `ms` milliseconds
+    pub fn jitter_threshold(mut self, ms: u64) -> Self {
+        self.jitter_threshold = Some(ms);
+        self
+    }
+
+    /// Subscribe to voice connection status events
+    pub async fn start<C: Connection>(&self, client: &mut Client<C>) -> Result<()> {
+        client
+            .subscribe(EventSubscribe::VoiceConnectionStatus)
+            .await
+    }
+
+    /// The last known connection state
+    pub fn state(&self) -> Option<VoiceConnectionState> {
+        self.state
+    }
+
+    /// The voice server
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    /// Statistics over the recent pings, `None` before the first ping
+    pub fn stats(&self) -> Option<PingStats> {
+        let last = *self.samples.back()?;
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let jitter = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(a, b)| a.abs_diff(*b))
+            .sum::<u64>() as f64
+            / (n - 1).max(1) as f64;
+        Some(PingStats {
+            last,
+            average: sorted.iter().sum::<u64>() as f64 / n as f64,
+            jitter,
+            median: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            max: sorted[n - 1],
+            samples: n,
+        })
+    }
+
+    /// Update from an event, returning the alerts it raised. Other events are ignored
+    pub fn handle(&mut self, event: &EventResponse) -> Vec<ConnectionAlert> {
+        let EventResponse::VoiceConnectionStatus(status) = event else {
+            return Vec::new();
+        };
+        let mut alerts = Vec::new();
+        if self.state != Some(status.state) {
+            self.state = Some(status.state);
+            alerts.push(ConnectionAlert::State(status.state));
+        }
+        if self.hostname != status.hostname {
+            debug!("Voice server changed to {:?}", status.hostname);
+            self.hostname.clone_from(&status.hostname);
+            self.samples.clear();
+            self.pings.clear();
+        }
+        // Without `pings`, every status is assumed to carry a new ping
+        let new_ping = status.pings.is_empty() || status.pings != self.pings;
+        self.pings.clone_from(&status.pings);
+        let ping = status.last_ping.or(status.pings.last().map(|p| p.value));
+        if let (VoiceConnectionState::VoiceConnected, Some(ping), true) =
+            (status.state, ping, new_ping)
+        {
+            if self.samples.len() == self.window {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(ping);
+        }
+        let Some(stats) = self.stats() else {
+            return alerts;
+        };
+        let was_degraded = self.high_ping || self.high_jitter;
+        let high_ping = self
+            .ping_threshold
+            .is_some_and(|t| stats.average > t as f64);
+        if high_ping && !self.high_ping {
+            alerts.push(ConnectionAlert::HighPing(stats));
+        }
+        let high_jitter = self
+            .jitter_threshold
+            .is_some_and(|t| stats.jitter > t as f64);
+        if high_jitter && !self.high_jitter {
+            alerts.push(ConnectionAlert::HighJitter(stats));
+        }
+        self.high_ping = high_ping;
+        self.high_jitter = high_jitter;
+        if was_degraded && !high_ping && !high_jitter {
+            alerts.push(ConnectionAlert::Recovered(stats));
+        }
+        alerts
+    }
+}
+
+/// Nearest rank percentile of sorted, non-empty samples
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn connection_monitor() {
+        use crate::command::VoiceConnectionStatus;
+
+        let status: VoiceConnectionStatus = serde_json::from_value(json!({
+            "state": "VOICE_CONNECTED",
+            "hostname": "eu-west1.discord.media",
+            "pings": [{ "time": 1700000000000u64, "value": 40 }, 45],
+            "average_ping": 42.5,
+            "last_ping": 45
+        }))
+        .unwrap();
+        assert_eq!(status.state, VoiceConnectionState::VoiceConnected);
+        assert!(status.pings[0].time.is_some());
+        assert_eq!(status.pings[1].value, 45);
+        let state: VoiceConnectionState = serde_json::from_value(json!("NEW_STATE")).unwrap();
+        assert_eq!(state, VoiceConnectionState::Unknown);
+
+        let event = |state, last_ping: Option<u64>| {
+            EventResponse::VoiceConnectionStatus(VoiceConnectionStatus {
+                state,
+                last_ping,
+                pings: last_ping
+                    .map(|value| vec![Ping { time: None, value }])
+                    .unwrap_or_default(),
+                ..status.clone()
+            })
+        };
+        let mut monitor = ConnectionMonitor::new()
+            .window(4)
+            .ping_threshold(100)
+            .jitter_threshold(50);
+        assert_eq!(
+            monitor.handle(&event(VoiceConnectionState::VoiceConnected, Some(40))),
+            [ConnectionAlert::State(VoiceConnectionState::VoiceConnected)]
+        );
+        assert!(monitor
+            .handle(&event(VoiceConnectionState::VoiceConnected, Some(60)))
+            .is_empty());
+        let alerts = monitor.handle(&event(VoiceConnectionState::VoiceConnected, Some(300)));
+        assert!(
+            matches!(alerts[..], [ConnectionAlert::HighPing(s), ConnectionAlert::HighJitter(_)] if s.max == 300)
+        );
+        // Only alerted once
+        assert!(monitor
+            .handle(&event(VoiceConnectionState::VoiceConnected, Some(290)))
+            .is_empty());
+        let stats = monitor.stats().unwrap();
+        assert_eq!((stats.median, stats.p95, stats.samples), (60, 300, 4));
+        assert_eq!(stats.average, 172.5);
+
+        for ping in [40, 45, 40] {
+            monitor.handle(&event(VoiceConnectionState::VoiceConnected, Some(ping)));
+        }
+        let alerts = monitor.handle(&event(VoiceConnectionState::VoiceConnected, Some(42)));
+        assert!(matches!(alerts[..], [ConnectionAlert::Recovered(s)] if s.jitter < 50.0));
+
+        // Pings aren't kept while disconnected
+        monitor.handle(&event(VoiceConnectionState::NoRoute, Some(1000)));
+        assert_eq!(monitor.stats().unwrap().last, 42);
+    }
+
+    #[test]
+    fn repeated_statuses() {
+        use crate::command::VoiceConnectionStatus;
+
+        let status: VoiceConnectionStatus = serde_json::from_value(json!({
+            "state": "VOICE_CONNECTED",
+            "pings": [40, 45],
+            "last_ping": 45
+        }))
+        .unwrap();
+        // Repeated statuses don't carry a new ping
+        let mut monitor = ConnectionMonitor::new();
+        for _ in 0..3 {
+            monitor.handle(&EventResponse::VoiceConnectionStatus(status.clone()));
+        }
+        assert_eq!(monitor.stats().unwrap().samples, 1);
+
+        let mut pings = status.pings.clone();
+        pings.push(Ping {
+            time: None,
+            value: 50,
+        });
+        monitor.handle(&EventResponse::VoiceConnectionStatus(
+            VoiceConnectionStatus {
+                pings,
+                last_ping: Some(50),
+                ..status
+            },
+        ));
+        assert_eq!(monitor.stats().unwrap().samples, 2);
+    }
+}
@@ -0,0 +1,381 @@
+//! Members of the selected voice channel
+
+use std::collections::HashMap;
+
+use log::*;
+
+use super::VoiceState;
+use crate::{
+    command::{EventResponse, VoiceChannelSelect},
+    discord::Snowflake,
+    Client, Connection, Error, EventSubscribe, Result,
+};
+
+/// Member of a [`VoiceRoster`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RosterMember {
+    /// Latest voice state
+    pub state: VoiceState,
+    /// Whether the member is speaking
+    pub speaking: bool,
+}
+
+impl RosterMember {
+    /// Whether the member is muted, by themselves or by the server
+    pub fn muted(&self) -> bool {
+        let flags = self.state.voice_state;
+        flags.mute || flags.self_mute
+    }
+
+    /// Whether the member is deafened, by themselves or by the server
+    pub fn deafened(&self) -> bool {
+        let flags = self.state.voice_state;
+        flags.deaf || flags.self_deaf
+    }
+}
+
+/// Change to a [`VoiceRoster`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RosterChange {
+    /// The user joined a voice channel, or left with `None`. The members were replaced
+    Channel(Option<Snowflake>),
+    /// A member joined the channel
+    Joined(Snowflake),
+    /// A member's voice state changed, e.g. they muted
+    Updated(Snowflake),
+    /// A member left the channel
+    Left(Snowflake),
+    /// A member started speaking
+    SpeakingStarted(Snowflake),
+    /// A member stopped speaking
+    SpeakingStopped(Snowflake),
+}
+
+/// Members of the user's voice channel, for "who's in voice" overlays
+///
+/// The roster follows the user between channels: [`start`](Self::start) subscribes to
+/// `VOICE_CHANNEL_SELECT`, and each channel's voice state & speaking events are subscribed to
+/// while the user is in it. Pass every event to [`handle`](Self::handle) to keep it up to date.
+/// Requires the `rpc` & `rpc.voice.read` scopes.
+///
+/// ```no_run
+/// # async fn f(client: &mut discord_ipc::Client<impl discord_ipc::Connection>) -> discord_ipc::Result<()> {
+/// use discord_ipc::voice::VoiceRoster;
+///
+/// let mut roster = VoiceRoster::new();
+/// roster.start(client).await?;
+/// loop {
+///     let event = client.event().await?;
+///     for change in roster.handle(client, &event).await? {
+///         println!("{change:?}");
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VoiceRoster {
+    channel: Option<Snowflake>,
+    members: HashMap<Snowflake, RosterMember>,
+}
+
+impl VoiceRoster {
+    /// Create an empty roster
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The user's voice channel
+    pub fn channel(&self) -> Option<Snowflake> {
+        self.channel
+    }
+
+    /// Members of the channel, by user ID
+    pub fn members(&self) -> &HashMap<Snowflake, RosterMember> {
+        &self.members
+    }
+
+    /// A member of the channel
+    pub fn member(&self, user_id: Snowflake) -> Option<&RosterMember> {
+        self.members.get(&user_id)
+    }
+
+    /// Subscribe to channel changes and load the user's current channel
+    pub async fn start<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+    ) -> Result<Vec<RosterChange>> {
+        client.subscribe(EventSubscribe::VoiceChannelSelect).await?;
+        let channel = client.get_selected_channel().await?;
+        let (id, states) = channel.map(|c| (c.id, c.voice_states)).unzip();
+        self.select(client, id, states).await
+    }
+
+    /// Update the roster from an event, returning what changed. Events unrelated to voice are
+    /// ignored
+    pub async fn handle<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+        event: &EventResponse,
+    ) -> Result<Vec<RosterChange>> {
+        if let EventResponse::VoiceChannelSelect(VoiceChannelSelect { channel_id, .. }) = event {
+            if *channel_id != self.channel {
+                return self.select(client, *channel_id, None).await;
+            }
+            return Ok(vec![]);
+        }
+        Ok(self.apply(event).into_iter().collect())
+    }
+
+    /// Move to `channel`, moving the subscriptions and loading its members, unless its voice
+    /// `states` were already fetched
+    async fn select<C: Connection>(
+        &mut self,
+        client: &mut Client<C>,
+        channel: Option<Snowflake>,
+        states: Option<Vec<VoiceState>>,
+    ) -> Result<Vec<RosterChange>> {
+        if let Some(old) = self.channel.take() {
+            for event in channel_events(old) {
+                // Discord may have dropped the subscriptions along with the channel
+                match client.unsubscribe(event).await {
+                    Ok(()) | Err(Error::Discord(_)) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            // Voice state events don't say which channel they're for, but any still queued were
+            // received before the old channel was unsubscribed from
+            client.framed.discard_events(|e| {
+                matches!(
+                    e,
+                    EventResponse::VoiceStateCreate(_)
+                        | EventResponse::VoiceStateUpdate(_)
+                        | EventResponse::VoiceStateDelete(_)
+                        | EventResponse::SpeakingStart(_)
+                        | EventResponse::SpeakingStop(_)
+                )
+            });
+        }
+        self.members.clear();
+        if let Some(id) = channel {
+            for event in channel_events(id) {
+                client.subscribe(event).await?;
+            }
+            self.channel = Some(id);
+            let states = match states {
+                Some(states) => states,
+                None => client.get_channel(id).await?.voice_states,
+            };
+            for state in states {
+                self.insert(state);
+            }
+        }
+        debug!("Voice roster moved to {channel:?}");
+        Ok(vec![RosterChange::Channel(channel)])
+    }
+
+    /// Insert or update a member, returning their ID. States without a user are ignored
+    fn insert(&mut self, state: VoiceState) -> Option<Snowflake> {
+        let Some(user_id) = state.user_id() else {
+            debug!("Ignoring voice state without a user: {state:?}");
+            return None;
+        };
+        let speaking = self.members.get(&user_id).is_some_and(|m| m.speaking);
+        self.members
+            .insert(user_id, RosterMember { state, speaking });
+        Some(user_id)
+    }
+
+    /// Apply a voice state or speaking event
+    fn apply(&mut self, event: &EventResponse) -> Option<RosterChange> {
+        match event {
+            EventResponse::VoiceStateCreate(state) | EventResponse::VoiceStateUpdate(state) => {
+                // Not following a channel
+                self.channel?;
+                let joined = !self.members.contains_key(&state.user_id()?);
+                let user_id = self.insert(state.clone())?;
+                Some(if joined {
+                    RosterChange::Joined(user_id)
+                } else {
+                    RosterChange::Updated(user_id)
+                })
+            }
+            EventResponse::VoiceStateDelete(state) => {
+                let user_id = state.user_id()?;
+                self.members
+                    .remove(&user_id)
+                    .map(|_| RosterChange::Left(user_id))
+            }
+            EventResponse::SpeakingStart(update) | EventResponse::SpeakingStop(update) => {
+                let speaking = matches!(event, EventResponse::SpeakingStart(_));
+                let member = self.members.get_mut(&update.user_id)?;
+                if member.speaking == speaking {
+                    return None;
+                }
+                member.speaking = speaking;
+                Some(if speaking {
+                    RosterChange::SpeakingStarted(update.user_id)
+                } else {
+                    RosterChange::SpeakingStopped(update.user_id)
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Subscriptions for the members of a channel
+fn channel_events(channel_id: Snowflake) -> [EventSubscribe; 5] {
+    [
+        EventSubscribe::VoiceStateCreate { channel_id },
+        EventSubscribe::VoiceStateUpdate { channel_id },
+        EventSubscribe::VoiceStateDelete { channel_id },
+        EventSubscribe::SpeakingStart { channel_id },
+        EventSubscribe::SpeakingStop { channel_id },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn missing_users() {
+        let state: VoiceState =
+            serde_json::from_value(json!({ "voice_state": { "self_mute": true } })).unwrap();
+        assert_eq!(state.user_id(), None);
+        assert_eq!(state.display_name(), None);
+
+        let state: VoiceState = serde_json::from_value(json!({
+            "nick": null,
+            "mute": true,
+            "volume": 50,
+            "pan": { "left": 1.0, "right": 0.5 },
+            "voice_state": {
+                "mute": true,
+                "deaf": false,
+                "self_mute": false,
+                "self_deaf": true,
+                "suppress": false
+            },
+            "user": { "id": "8", "username": "user", "global_name": "User", "avatar": null }
+        }))
+        .unwrap();
+        assert_eq!(state.user_id(), Some(Snowflake(8)));
+        assert_eq!(state.display_name(), Some("User"));
+        // Muted by the server and deafened by themselves, not just muted locally
+        let member = RosterMember {
+            state: state.clone(),
+            speaking: false,
+        };
+        assert!(member.muted() && member.deafened());
+
+        let mut roster = VoiceRoster {
+            channel: Some(Snowflake(10)),
+            ..Default::default()
+        };
+        let event = EventResponse::VoiceStateCreate(VoiceState {
+            user: None,
+            ..state
+        });
+        assert_eq!(roster.apply(&event), None);
+        assert!(roster.members().is_empty());
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    mod client {
+        use serde_json::json;
+
+        use super::*;
+        use crate::mock::{channel, client, voice_state, MockDiscord};
+
+        fn expect_subscriptions(discord: &mut MockDiscord, cmd: &str, channel_id: &str) {
+            for evt in [
+                "VOICE_STATE_CREATE",
+                "VOICE_STATE_UPDATE",
+                "VOICE_STATE_DELETE",
+                "SPEAKING_START",
+                "SPEAKING_STOP",
+            ] {
+                let payload = discord.expect_command(cmd);
+                assert_eq!(payload["evt"], evt);
+                assert_eq!(payload["args"]["channel_id"], channel_id);
+                discord.respond(cmd, json!({ "evt": evt }));
+            }
+        }
+
+        #[tokio::test]
+        async fn follows_the_selected_channel() {
+            let (socket, discord) = MockDiscord::spawn(|mut discord| {
+                discord.handshake();
+                discord.expect_command("SUBSCRIBE");
+                discord.respond("SUBSCRIBE", json!({ "evt": "VOICE_CHANNEL_SELECT" }));
+                discord.expect_command("GET_SELECTED_VOICE_CHANNEL");
+                discord.respond(
+                    "GET_SELECTED_VOICE_CHANNEL",
+                    channel("10", json!([voice_state("7", false)])),
+                );
+                // The selected channel is already loaded
+                expect_subscriptions(&mut discord, "SUBSCRIBE", "10");
+
+                discord.dispatch("VOICE_STATE_CREATE", voice_state("8", false));
+                discord.dispatch("SPEAKING_START", json!({ "user_id": "8" }));
+                discord.dispatch("VOICE_STATE_UPDATE", voice_state("8", true));
+                discord.dispatch("SPEAKING_STOP", json!({ "user_id": "8" }));
+                discord.dispatch("VOICE_STATE_DELETE", voice_state("8", true));
+                discord.dispatch(
+                    "VOICE_CHANNEL_SELECT",
+                    json!({ "channel_id": "11", "guild_id": "1" }),
+                );
+                // Still sent for the previous channel
+                discord.dispatch("VOICE_STATE_CREATE", voice_state("9", false));
+                expect_subscriptions(&mut discord, "UNSUBSCRIBE", "10");
+                expect_subscriptions(&mut discord, "SUBSCRIBE", "11");
+                discord.expect_command("GET_CHANNEL");
+                discord.respond("GET_CHANNEL", channel("11", json!([])));
+                discord.dispatch(
+                    "VOICE_CHANNEL_SELECT",
+                    json!({ "channel_id": "11", "guild_id": "1" }),
+                );
+            });
+            let mut client = client(socket).await;
+
+            let mut roster = VoiceRoster::new();
+            assert_eq!(
+                roster.start(&mut client).await.unwrap(),
+                [RosterChange::Channel(Some(Snowflake(10)))]
+            );
+            assert_eq!(roster.members().len(), 1);
+
+            let mut changes = vec![];
+            for _ in 0..6 {
+                let event = client.event().await.unwrap();
+                changes.extend(roster.handle(&mut client, &event).await.unwrap());
+                if changes.len() == 3 {
+                    let member = roster.member(Snowflake(8)).unwrap();
+                    assert!(member.muted() && member.speaking);
+                }
+            }
+            // The previous channel's event was dropped
+            assert!(matches!(
+                client.event().await.unwrap(),
+                EventResponse::VoiceChannelSelect(_)
+            ));
+            discord.join().unwrap();
+            assert_eq!(
+                changes,
+                [
+                    RosterChange::Joined(Snowflake(8)),
+                    RosterChange::SpeakingStarted(Snowflake(8)),
+                    RosterChange::Updated(Snowflake(8)),
+                    RosterChange::SpeakingStopped(Snowflake(8)),
+                    RosterChange::Left(Snowflake(8)),
+                    RosterChange::Channel(Some(Snowflake(11))),
+                ]
+            );
+            assert_eq!(roster.channel(), Some(Snowflake(11)));
+            assert!(roster.members().is_empty());
+        }
+    }
+}
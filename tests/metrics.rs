@@ -0,0 +1,73 @@
+//! Counters are process wide, so they are checked in their own test binary, where no other test
+//! connects concurrently
+
+#![cfg(all(unix, feature = "tokio"))]
+
+#[path = "../src/mock.rs"]
+mod mock;
+
+use discord_ipc::{
+    codec::OpCode,
+    metrics::{self, Metrics},
+    Client, Error,
+};
+use mock::{tokio_socket, MockDiscord};
+use serde_json::json;
+
+#[tokio::test]
+async fn counters() {
+    let (socket, discord) = MockDiscord::spawn(|mut discord| {
+        discord.handshake();
+        discord.expect_command("SET_ACTIVITY");
+        discord.dispatch("VOICE_CHANNEL_SELECT", json!({ "channel_id": null }));
+        discord.respond("SET_ACTIVITY", json!({ "name": "test", "type": 0 }));
+        let payload = discord.expect_command("SET_ACTIVITY");
+        discord.write_frame(
+            OpCode::FRAME,
+            json!({
+                "cmd": "SET_ACTIVITY",
+                "evt": "ERROR",
+                "data": { "code": 4000, "message": "Invalid payload" },
+                "nonce": payload["nonce"]
+            }),
+        );
+    });
+    let mut client = mock::client(socket).await;
+    client.set_activity("Testing").await.unwrap();
+    assert!(matches!(
+        client.set_activity("Testing").await,
+        Err(Error::Discord(_))
+    ));
+    client.event().await.unwrap();
+    discord.join().unwrap();
+    assert!(matches!(client.event().await, Err(Error::PipeClosed)));
+
+    let (socket, discord) = MockDiscord::spawn(|mut discord| {
+        discord.read_frame();
+        discord.write_frame(
+            OpCode::CLOSE,
+            json!({ "code": 4000, "message": "Invalid Client ID" }),
+        );
+    });
+    assert!(Client::new(42)
+        .connect_with(tokio_socket(socket))
+        .await
+        .is_err());
+    discord.join().unwrap();
+
+    let (socket, discord) = MockDiscord::spawn(|mut discord| discord.handshake());
+    mock::client(socket).await;
+    metrics::reconnected();
+    discord.join().unwrap();
+
+    assert_eq!(
+        metrics::snapshot(),
+        Metrics {
+            connects: 2,
+            reconnects: 1,
+            commands: 2,
+            events: 1,
+            errors: 3,
+        }
+    );
+}